@@ -0,0 +1,55 @@
+#!/bin/sh
+# vendor the pinned assets listed in src/assets.rs next to this script, each npm
+# package tarball is checked against the integrity hash the registry published
+# for it before anything is taken out of it, the files are then recorded in
+# SHA256SUMS, commit both and build with --features embedded-assets
+set -eu
+cd "$(dirname "$0")"
+
+REGISTRY=https://registry.npmjs.org
+WORK=$(mktemp -d)
+trap 'rm -rf "$WORK"' EXIT
+
+# fetch <package> <version> then the files wanted out of it as <path in package> <target>...
+fetch() {
+    package=$1
+    version=$2
+    shift 2
+    tarball="$WORK/$package-$version.tgz"
+
+    # "integrity":"sha512-<base64>" from the version's metadata
+    integrity=$(curl -fsSL "$REGISTRY/$package/$version" |
+        sed -n 's/.*"integrity":"\(sha512-[^"]*\)".*/\1/p')
+    if [ -z "$integrity" ]; then
+        echo "no published integrity for $package@$version" >&2
+        exit 1
+    fi
+    curl -fsSL -o "$tarball" "$REGISTRY/$package/-/$package-$version.tgz"
+    actual="sha512-$(openssl dgst -sha512 -binary "$tarball" | base64 | tr -d '\n')"
+    if [ "$actual" != "$integrity" ]; then
+        echo "$package@$version does not match its published integrity" >&2
+        exit 1
+    fi
+
+    mkdir -p "$WORK/$package"
+    tar -xzf "$tarball" -C "$WORK/$package"
+    while [ $# -gt 0 ]; do
+        mkdir -p "$(dirname "$2")"
+        cp "$WORK/$package/package/$1" "$2"
+        echo "$2" >> "$WORK/files"
+        shift 2
+    done
+}
+
+fetch todomvc-common 1.0.5 \
+    base.css todomvc-common/base.css \
+    base.js todomvc-common/base.js
+fetch todomvc-app-css 2.4.3 \
+    index.css todomvc-app-css/index.css
+fetch htmx.org 1.9.10 \
+    dist/htmx.min.js htmx/htmx.min.js
+fetch hyperscript.org 0.9.12 \
+    dist/_hyperscript.min.js hyperscript/_hyperscript.min.js
+
+# shellcheck disable=SC2046
+sha256sum $(cat "$WORK/files") > SHA256SUMS
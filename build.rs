@@ -0,0 +1,80 @@
+// with the embedded-assets feature every file vendored in assets/ is checked against
+// assets/SHA256SUMS and get a gzip and a brotli copy in OUT_DIR, compressed once here
+// at the best quality so the binary can hand them out as is instead of compressing
+// the same bytes on every request
+use flate2::{write::GzEncoder, Compression};
+use sha2::{Digest, Sha256};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+};
+
+fn compress(bytes: &[u8], target: &Path) -> io::Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut gzip = GzEncoder::new(Vec::new(), Compression::best());
+    gzip.write_all(bytes)?;
+    fs::write(
+        target.with_extension(extension(target, "gz")),
+        gzip.finish()?,
+    )?;
+
+    let mut brotli = brotli::CompressorWriter::new(Vec::new(), 4096, 11, 22);
+    brotli.write_all(bytes)?;
+    fs::write(
+        target.with_extension(extension(target, "br")),
+        brotli.into_inner(),
+    )?;
+    Ok(())
+}
+
+// base.css -> css.gz so the copy sit next to the original name as base.css.gz
+fn extension(path: &Path, suffix: &str) -> String {
+    match path.extension() {
+        Some(ext) => format!("{}.{}", ext.to_string_lossy(), suffix),
+        None => suffix.to_string(),
+    }
+}
+
+// a line of sha256sum output, `<hex>  <file>` or `<hex> *<file>` in binary mode
+fn checksum_line(line: &str) -> Option<(&str, &str)> {
+    let (sum, file) = line.split_once(' ')?;
+    Some((sum, file.trim_start_matches([' ', '*'])))
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_EMBEDDED_ASSETS").is_none() {
+        return;
+    }
+    println!("cargo:rerun-if-changed=assets");
+    let assets = Path::new("assets");
+    let out_dir = Path::new(&env::var("OUT_DIR").unwrap()).join("assets");
+    let sums = fs::read_to_string(assets.join("SHA256SUMS")).unwrap_or_else(|e| {
+        panic!(
+            "reading assets/SHA256SUMS: {}, run assets/fetch.sh to vendor the assets \
+             or build without the embedded-assets feature to load them from the cdn",
+            e
+        )
+    });
+    // the checksums are the list of files, a vendored file that was edited or
+    // swapped for another version fail the build instead of shipping
+    for line in sums.lines().filter(|line| !line.trim().is_empty()) {
+        let (expected, file) = checksum_line(line)
+            .unwrap_or_else(|| panic!("malformed line in assets/SHA256SUMS: {:?}", line));
+        let bytes = fs::read(assets.join(file))
+            .unwrap_or_else(|e| panic!("reading assets/{}: {}", file, e));
+        let actual = format!("{:x}", Sha256::digest(&bytes));
+        if actual != expected {
+            panic!(
+                "assets/{} does not match assets/SHA256SUMS, expected {} got {}",
+                file, expected, actual
+            );
+        }
+        compress(&bytes, &out_dir.join(file))
+            .unwrap_or_else(|e| panic!("compressing assets/{}: {}", file, e));
+    }
+}
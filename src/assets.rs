@@ -0,0 +1,140 @@
+// the css and scripts the page need, pinned to the versions the app is tested with,
+// with the embedded-assets feature they are compiled into the binary
+// from assets/ (vendored with assets/fetch.sh, checked against assets/SHA256SUMS)
+// so the app work without internet access, build.rs add a gzip and a brotli copy
+// of each so they are never compressed at runtime
+use crate::compression::Encoding;
+
+pub struct Asset {
+    // where the app serve it when embedded, the version is part of the path so
+    // browsers can cache it for good and still pick up an upgrade
+    pub path: &'static str,
+    pub cdn: &'static str,
+    pub content_type: &'static str,
+    pub embedded: Option<Embedded>,
+}
+
+pub struct Embedded {
+    pub raw: &'static [u8],
+    pub gzip: &'static [u8],
+    pub brotli: &'static [u8],
+}
+
+impl Embedded {
+    pub fn encoded(&self, encoding: Option<Encoding>) -> &'static [u8] {
+        match encoding {
+            Some(Encoding::Brotli) => self.brotli,
+            Some(Encoding::Gzip) => self.gzip,
+            None => self.raw,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-assets")]
+macro_rules! embed {
+    ($file:literal) => {
+        Some(Embedded {
+            raw: include_bytes!(concat!("../assets/", $file)),
+            gzip: include_bytes!(concat!(env!("OUT_DIR"), "/assets/", $file, ".gz")),
+            brotli: include_bytes!(concat!(env!("OUT_DIR"), "/assets/", $file, ".br")),
+        })
+    };
+}
+
+#[cfg(not(feature = "embedded-assets"))]
+macro_rules! embed {
+    ($file:literal) => {
+        None
+    };
+}
+
+pub const BASE_CSS: Asset = Asset {
+    path: "/assets/todomvc-common@1.0.5/base.css",
+    cdn: "https://unpkg.com/todomvc-common@1.0.5/base.css",
+    content_type: "text/css; charset=utf-8",
+    embedded: embed!("todomvc-common/base.css"),
+};
+
+pub const APP_CSS: Asset = Asset {
+    path: "/assets/todomvc-app-css@2.4.3/index.css",
+    cdn: "https://unpkg.com/todomvc-app-css@2.4.3/index.css",
+    content_type: "text/css; charset=utf-8",
+    embedded: embed!("todomvc-app-css/index.css"),
+};
+
+pub const BASE_JS: Asset = Asset {
+    path: "/assets/todomvc-common@1.0.5/base.js",
+    cdn: "https://unpkg.com/todomvc-common@1.0.5/base.js",
+    content_type: "application/javascript",
+    embedded: embed!("todomvc-common/base.js"),
+};
+
+pub const HTMX_JS: Asset = Asset {
+    path: "/assets/htmx.org@1.9.10/htmx.min.js",
+    cdn: "https://unpkg.com/htmx.org@1.9.10/dist/htmx.min.js",
+    content_type: "application/javascript",
+    embedded: embed!("htmx/htmx.min.js"),
+};
+
+pub const HYPERSCRIPT_JS: Asset = Asset {
+    path: "/assets/hyperscript.org@0.9.12/_hyperscript.min.js",
+    cdn: "https://unpkg.com/hyperscript.org@0.9.12/dist/_hyperscript.min.js",
+    content_type: "application/javascript",
+    embedded: embed!("hyperscript/_hyperscript.min.js"),
+};
+
+pub const ASSETS: [&Asset; 5] = [&BASE_CSS, &APP_CSS, &BASE_JS, &HTMX_JS, &HYPERSCRIPT_JS];
+
+// where the page load the assets from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetSource {
+    Embedded,
+    Cdn,
+}
+
+impl AssetSource {
+    // embedded when the binary carry the assets, the cdn otherwise
+    pub fn default_source() -> AssetSource {
+        if cfg!(feature = "embedded-assets") {
+            AssetSource::Embedded
+        } else {
+            AssetSource::Cdn
+        }
+    }
+
+    pub fn url(self, asset: &Asset) -> &'static str {
+        match self {
+            AssetSource::Embedded => asset.path,
+            AssetSource::Cdn => asset.cdn,
+        }
+    }
+}
+
+pub fn parse_source(value: &str) -> Result<AssetSource, String> {
+    match value {
+        "embedded" if cfg!(feature = "embedded-assets") => Ok(AssetSource::Embedded),
+        "embedded" => Err("ASSETS=embedded need the embedded-assets feature".to_string()),
+        "cdn" => Ok(AssetSource::Cdn),
+        _ => Err(format!(
+            "invalid ASSETS {:?}, expected embedded or cdn",
+            value
+        )),
+    }
+}
+
+// embedded need every asset compiled in, the cdn is the browser's business
+pub fn loaded(source: AssetSource) -> bool {
+    match source {
+        AssetSource::Embedded => ASSETS.into_iter().all(|asset| {
+            asset
+                .embedded
+                .as_ref()
+                .is_some_and(|embedded| !embedded.raw.is_empty())
+        }),
+        AssetSource::Cdn => true,
+    }
+}
+
+pub fn find(path: &str) -> Option<&'static Asset> {
+    ASSETS.into_iter().find(|asset| asset.path == path)
+}
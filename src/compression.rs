@@ -0,0 +1,168 @@
+use astra::{Body, Request, Response};
+use flate2::{write::GzEncoder, Compression};
+use std::io::{self, Read, Write};
+
+// below this the headers and the cpu cost more than the bytes saved
+pub const MIN_SIZE: usize = 1024;
+// compressing mean holding the whole body in memory, a bigger one (a large
+// static file) or one of unknown size keep streaming as it is
+const MAX_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    // quality 5 keep brotli quick enough for pages rendered on every request,
+    // the embedded assets are compressed at build time with the best quality
+    pub fn encode(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut writer = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+                writer.write_all(bytes)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+// pick from Accept-Encoding, brotli when the client like it at least as much as gzip,
+// q=0 rule an encoding out and * stand for anything not listed
+pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+    let mut brotli = None;
+    let mut gzip = None;
+    let mut any = None;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';').map(|part| part.trim());
+        let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match name.as_str() {
+            "br" => brotli = Some(quality),
+            "gzip" | "x-gzip" => gzip = Some(quality),
+            "*" => any = Some(quality),
+            _ => {}
+        }
+    }
+    let brotli = brotli.or(any).unwrap_or(0.0);
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    if brotli > 0.0 && brotli >= gzip {
+        Some(Encoding::Brotli)
+    } else if gzip > 0.0 {
+        Some(Encoding::Gzip)
+    } else {
+        None
+    }
+}
+
+pub fn accepted(req: &Request) -> Option<Encoding> {
+    req.headers()
+        .get("Accept-Encoding")
+        .and_then(|value| value.to_str().ok())
+        .and_then(negotiate)
+}
+
+// text compress well, images and fonts are already compressed
+fn is_compressible(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    media_type.starts_with("text/")
+        || matches!(
+            media_type,
+            "application/javascript" | "application/json" | "image/svg+xml"
+        )
+}
+
+fn vary(res: &mut Response) {
+    if let Ok(header_value) = "Accept-Encoding".parse() {
+        res.headers_mut().append("Vary", header_value);
+    }
+}
+
+// compress a handler's response for the client, anything already encoded (the
+// precompressed assets), not a 200 or not worth it is left as it is, every
+// compressible 200 get its Vary here so no handler has to set it
+pub fn compress(encoding: Option<Encoding>, mut res: Response) -> io::Result<Response> {
+    let content_type = res
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if res.status() != 200 || !is_compressible(content_type) {
+        return Ok(res);
+    }
+    vary(&mut res);
+    if res.headers().contains_key("Content-Encoding") {
+        return Ok(res);
+    }
+    let Some(encoding) = encoding else {
+        return Ok(res);
+    };
+    let content_length = res
+        .headers()
+        .get("Content-Length")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if !content_length.is_some_and(|len| (MIN_SIZE..=MAX_SIZE).contains(&len)) {
+        return Ok(res);
+    }
+
+    let mut bytes = Vec::new();
+    res.body_mut().reader().read_to_end(&mut bytes)?;
+    if bytes.len() < MIN_SIZE {
+        *res.body_mut() = Body::new(bytes);
+        return Ok(res);
+    }
+    let compressed = encoding.encode(&bytes)?;
+    if let Ok(header_value) = encoding.name().parse() {
+        res.headers_mut().insert("Content-Encoding", header_value);
+    }
+    res.headers_mut()
+        .insert("Content-Length", compressed.len().into());
+    *res.body_mut() = Body::new(compressed);
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiate_prefer_brotli_on_a_tie() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0.5, gzip;q=0.8"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn negotiate_q0_rule_an_encoding_out() {
+        assert_eq!(negotiate("br;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("br;q=0, gzip;q=0"), None);
+        assert_eq!(negotiate("GZIP; q=0.0"), None);
+    }
+
+    #[test]
+    fn negotiate_star_stand_for_anything_not_listed() {
+        assert_eq!(negotiate("*"), Some(Encoding::Brotli));
+        assert_eq!(negotiate("br;q=0, *"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(negotiate("gzip, *;q=0"), Some(Encoding::Gzip));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::assets::{parse_source, AssetSource};
+use crate::session::{parse_key, parse_lifetime, DEFAULT_LIFETIME};
+use clap::{error::ErrorKind, CommandFactory, Parser, ValueEnum};
+use cookie::Key;
+use serde::Deserialize;
+use std::{fs, path::PathBuf, time::Duration};
+
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// every setting can come from a flag, an environment variable or the toml file
+// given with --config, in that order of precedence, and fall back to a default,
+// the environment variables are the ones the app always read so nothing break
+#[derive(Parser, Debug)]
+#[command(
+    name = "todomvc-htmx",
+    version,
+    about = "TodoMVC built with astra, maud, htmx and _hyperscript"
+)]
+struct Args {
+    #[arg(long, env = "CONFIG", help = "read settings from this toml file")]
+    config: Option<PathBuf>,
+    #[arg(long, env = "HOST", help = "address to listen on [default: localhost]")]
+    host: Option<String>,
+    #[arg(long, env = "PORT", help = "port to listen on [default: 8888]")]
+    port: Option<u16>,
+    #[arg(
+        long,
+        env = "WORKERS",
+        help = "maximum number of worker threads [default: astra's]"
+    )]
+    workers: Option<usize>,
+    #[arg(
+        long,
+        env = "STORAGE",
+        help = "where the todos are kept: memory, json, eventlog or sqlite [default: memory, json when --data-dir is set]"
+    )]
+    storage: Option<String>,
+    #[arg(
+        long,
+        env = "DATA_DIR",
+        help = "folder for the json and eventlog storage [default: data]"
+    )]
+    data_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        env = "DATABASE_PATH",
+        help = "database file for the sqlite storage [default: todos.db]"
+    )]
+    database_path: Option<PathBuf>,
+    #[arg(
+        long,
+        env = "SESSION_LIFETIME",
+        help = "idle seconds before a session is evicted, or never [default: 600]"
+    )]
+    session_lifetime: Option<String>,
+    #[arg(
+        long,
+        env = "SESSION_KEY",
+        hide_env_values = true,
+        help = "secret of at least 64 bytes signing the session cookie [default: random]"
+    )]
+    session_key: Option<String>,
+    #[arg(
+        long,
+        env = "SECURE_COOKIE",
+        value_parser = clap::builder::BoolishValueParser::new(),
+        help = "mark the session cookie secure, when served over https [default: false]"
+    )]
+    secure_cookie: Option<bool>,
+    #[arg(
+        long,
+        env = "ASSETS",
+        help = "load htmx, _hyperscript and the css from embedded or cdn [default: embedded when built in, cdn otherwise]"
+    )]
+    assets: Option<String>,
+    #[arg(
+        long,
+        env = "STATIC_DIR",
+        help = "folder /node_modules/... is served from [default: cypress-example-todomvc]"
+    )]
+    static_dir: Option<PathBuf>,
+    #[arg(long, env = "TITLE", help = "page title [default: HTMX • TodoMVC]")]
+    title: Option<String>,
+    #[arg(
+        long,
+        env = "LOG_LEVEL",
+        value_enum,
+        help = "how much to log [default: info]"
+    )]
+    log_level: Option<LogLevel>,
+    #[arg(
+        long,
+        env = "LOG_FORMAT",
+        value_enum,
+        help = "plain text or one json object per line [default: plain]"
+    )]
+    log_format: Option<LogFormat>,
+    #[arg(
+        long,
+        env = "SHUTDOWN_TIMEOUT",
+        help = "seconds to let running requests finish on SIGINT/SIGTERM [default: 10]"
+    )]
+    shutdown_timeout: Option<u64>,
+}
+
+// the same settings as the flags, all optional
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    host: Option<String>,
+    port: Option<u16>,
+    workers: Option<usize>,
+    storage: Option<String>,
+    data_dir: Option<PathBuf>,
+    database_path: Option<PathBuf>,
+    session_lifetime: Option<Lifetime>,
+    session_key: Option<String>,
+    secure_cookie: Option<bool>,
+    assets: Option<String>,
+    static_dir: Option<PathBuf>,
+    title: Option<String>,
+    log_level: Option<LogLevel>,
+    log_format: Option<LogFormat>,
+    shutdown_timeout: Option<u64>,
+}
+
+// session_lifetime = 600 and session_lifetime = "never" both read naturally in toml
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum Lifetime {
+    Seconds(u64),
+    Text(String),
+}
+
+impl Lifetime {
+    fn into_string(self) -> String {
+        match self {
+            Lifetime::Seconds(secs) => secs.to_string(),
+            Lifetime::Text(text) => text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Plain,
+    Json,
+}
+
+pub enum Storage {
+    Memory,
+    Json(PathBuf),
+    EventLog(PathBuf),
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf),
+}
+
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub workers: Option<usize>,
+    pub storage: Storage,
+    pub session_lifetime: Option<Duration>,
+    // None generate a random key, every cookie handed out before a restart become invalid
+    pub session_key: Option<Key>,
+    pub secure_cookie: bool,
+    pub assets: AssetSource,
+    pub static_dir: PathBuf,
+    pub title: String,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    pub shutdown_timeout: Duration,
+}
+
+impl Config {
+    // read the flags, the environment and the config file, print the usage and
+    // exit on --help or a bad value like any other command line tool
+    pub fn load() -> Config {
+        let args = Args::parse();
+        Config::resolve(args)
+            .unwrap_or_else(|e| Args::command().error(ErrorKind::ValueValidation, e).exit())
+    }
+
+    fn resolve(args: Args) -> Result<Config, String> {
+        let file = match &args.config {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read config {}: {}", path.display(), e))?;
+                toml::from_str(&text)
+                    .map_err(|e| format!("invalid config {}: {}", path.display(), e))?
+            }
+            None => FileConfig::default(),
+        };
+
+        let host = args
+            .host
+            .or(file.host)
+            .unwrap_or_else(|| "localhost".to_string());
+        if host.trim().is_empty() {
+            return Err("host can't be empty".to_string());
+        }
+        let port = args.port.or(file.port).unwrap_or(8888);
+        let workers = args.workers.or(file.workers);
+        if workers == Some(0) {
+            return Err("workers must be at least 1".to_string());
+        }
+
+        // setting only the data dir keep choosing json for backward compatibility
+        let data_dir = args.data_dir.or(file.data_dir);
+        let storage = match args.storage.or(file.storage) {
+            Some(storage) => storage,
+            None if data_dir.is_some() => "json".to_string(),
+            None => "memory".to_string(),
+        };
+        let data_dir = data_dir.unwrap_or_else(|| PathBuf::from("data"));
+        let database_path = args
+            .database_path
+            .or(file.database_path)
+            .unwrap_or_else(|| PathBuf::from("todos.db"));
+        let storage = match storage.as_str() {
+            "memory" => Storage::Memory,
+            "json" => Storage::Json(data_dir),
+            "eventlog" => Storage::EventLog(data_dir),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Storage::Sqlite(database_path),
+            #[cfg(not(feature = "sqlite"))]
+            "sqlite" => {
+                return Err(format!(
+                    "sqlite storage ({}) need the sqlite feature, build with --features sqlite",
+                    database_path.display()
+                ))
+            }
+            other => {
+                return Err(format!(
+                    "unknown storage {:?}, expected memory, json, eventlog or sqlite",
+                    other
+                ))
+            }
+        };
+
+        let session_lifetime = match args
+            .session_lifetime
+            .or(file.session_lifetime.map(Lifetime::into_string))
+        {
+            Some(value) => parse_lifetime(&value)?,
+            None => Some(DEFAULT_LIFETIME),
+        };
+        let session_key = args
+            .session_key
+            .or(file.session_key)
+            .map(|secret| parse_key(&secret))
+            .transpose()?;
+        let secure_cookie = args.secure_cookie.or(file.secure_cookie).unwrap_or(false);
+
+        // embedded need the embedded-assets feature and is the default when built with it
+        let assets = match args.assets.or(file.assets) {
+            Some(value) => parse_source(&value)?,
+            None => AssetSource::default_source(),
+        };
+        let static_dir = args
+            .static_dir
+            .or(file.static_dir)
+            .unwrap_or_else(|| PathBuf::from("cypress-example-todomvc"));
+        let title = args
+            .title
+            .or(file.title)
+            .unwrap_or_else(|| "HTMX • TodoMVC".to_string());
+        let log_level = args.log_level.or(file.log_level).unwrap_or(LogLevel::Info);
+        let log_format = args
+            .log_format
+            .or(file.log_format)
+            .unwrap_or(LogFormat::Plain);
+        let shutdown_timeout = args
+            .shutdown_timeout
+            .or(file.shutdown_timeout)
+            .map_or(DEFAULT_SHUTDOWN_TIMEOUT, Duration::from_secs);
+
+        Ok(Config {
+            host,
+            port,
+            workers,
+            storage,
+            session_lifetime,
+            session_key,
+            secure_cookie,
+            assets,
+            static_dir,
+            title,
+            log_level,
+            log_format,
+            shutdown_timeout,
+        })
+    }
+
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
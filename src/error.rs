@@ -0,0 +1,172 @@
+use crate::logging;
+use crate::store::StoreError;
+use crate::{response, with_hx, Hx};
+use astra::{Request, Response};
+use maud::{html, PreEscaped, DOCTYPE};
+use serde_json::{json, Value};
+use std::{error::Error, fmt, io};
+
+// everything a handler can fail with, each kind map to a status, the message is
+// what the client is told so it never carry anything internal, the storage and
+// internal errors keep their detail for the log only
+#[derive(Debug)]
+pub enum AppError {
+    // the request itself is wrong, a malformed id or a missing field
+    BadRequest(String),
+    NotFound(String),
+    // the todo changed under the request, trying again should work
+    Conflict(String),
+    UnsupportedMediaType(String),
+    PayloadTooLarge(String),
+    Storage(StoreError),
+    Internal(String),
+}
+
+impl AppError {
+    pub fn status(&self) -> u16 {
+        match self {
+            AppError::BadRequest(_) => 400,
+            AppError::NotFound(_) => 404,
+            AppError::Conflict(_) => 409,
+            AppError::UnsupportedMediaType(_) => 415,
+            AppError::PayloadTooLarge(_) => 413,
+            AppError::Storage(_) | AppError::Internal(_) => 500,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            AppError::BadRequest(_) => "Bad Request",
+            AppError::NotFound(_) => "Not Found",
+            AppError::Conflict(_) => "Conflict",
+            AppError::UnsupportedMediaType(_) => "Unsupported Media Type",
+            AppError::PayloadTooLarge(_) => "Payload Too Large",
+            AppError::Storage(_) | AppError::Internal(_) => "Internal Server Error",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            AppError::BadRequest(message)
+            | AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::UnsupportedMediaType(message)
+            | AppError::PayloadTooLarge(message) => message,
+            AppError::Storage(_) | AppError::Internal(_) => {
+                "something went wrong on our side, try again in a moment"
+            }
+        }
+    }
+
+    // a failure on our side is an error in the log, the client's own mistakes are
+    // only worth an info line, the access log already count them
+    fn log(&self) {
+        let fields = [
+            ("status", Value::from(self.status())),
+            ("error", Value::from(self.to_string())),
+        ];
+        if self.status() >= 500 {
+            logging::error("request failed", &fields);
+        } else {
+            logging::info("request rejected", &fields);
+        }
+    }
+
+    // log the error and answer in the shape the client can use:
+    // - json when the Accept header prefer it
+    // - for htmx a fragment swapped into the page's error box, whatever the
+    //   request was targeting
+    // - a small page otherwise, a plain form post land there
+    pub fn response(&self, req: &Request) -> Response {
+        self.log();
+        let status = self.status();
+        if wants_json(req) {
+            let body = json!({
+                "error": {
+                    "status": status,
+                    "title": self.title(),
+                    "message": self.message(),
+                }
+            });
+            return response(
+                status,
+                PreEscaped(body.to_string()),
+                Some("application/json"),
+            );
+        }
+        let headers = req.headers();
+        if headers.contains_key("HX-Request") && !headers.contains_key("HX-History-Restore-Request")
+        {
+            let mk = html! {
+                p class="error" _="on load wait 5s then remove me" {
+                    strong { (self.title()) } " " (self.message())
+                }
+            };
+            return with_hx(
+                response(status, mk, None),
+                vec![Hx::Retarget("#errors"), Hx::Reswap("innerHTML")],
+            );
+        }
+        let mk = html! {
+            (DOCTYPE)
+            html lang="en" {
+                head {
+                    meta charset="utf-8";
+                    title { (status) " " (self.title()) }
+                }
+                body {
+                    h1 { (status) " " (self.title()) }
+                    p { (self.message()) }
+                    p { a href="/" { "Back to your todos" } }
+                }
+            }
+        };
+        response(status, mk, None)
+    }
+}
+
+// json only when it is asked for ahead of html, a browser send both and want the html
+fn wants_json(req: &Request) -> bool {
+    let Some(accept) = req
+        .headers()
+        .get("Accept")
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    match (accept.find("application/json"), accept.find("text/html")) {
+        (Some(json), Some(html)) => json < html,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Storage(e) => write!(f, "{}", e),
+            AppError::Internal(detail) => write!(f, "internal error: {}", detail),
+            other => write!(f, "{}: {}", other.title().to_lowercase(), other.message()),
+        }
+    }
+}
+
+impl Error for AppError {}
+
+impl From<StoreError> for AppError {
+    fn from(e: StoreError) -> Self {
+        AppError::Storage(e)
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(e: serde_json::Error) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
+
+impl From<io::Error> for AppError {
+    fn from(e: io::Error) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
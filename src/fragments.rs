@@ -1,16 +1,23 @@
-use crate::{Filter, Todo};
+use crate::assets::{AssetSource, APP_CSS, BASE_CSS, BASE_JS, HTMX_JS, HYPERSCRIPT_JS};
+use crate::{Todo, TodoFilter};
 use maud::{html, Markup, PreEscaped, DOCTYPE};
 
-pub fn clear_completed(has_completed: bool) -> Markup {
+// every control sit in a real form posting to the server so the app still work
+// with scripting disabled, htmx take the request over when it is there and the
+// server answer a plain form post with a redirect back to the page
+
+fn clear_completed(has_completed: bool) -> Markup {
     html! {
         @if has_completed {
-            button
-                class="clear-completed"
-                _="
-                    on load set $clearCompleted to me
-                    on click send destroy to <li.completed/>
-                " { 
-                "Clear completed"
+            form method="post" action="/clear-completed" {
+                button
+                    type="submit"
+                    class="clear-completed"
+                    hx-delete="/clear-completed"
+                    hx-target=".todo-list"
+                    hx-swap="innerHTML" {
+                    "Clear completed"
+                }
             }
         }
     }
@@ -18,36 +25,42 @@ pub fn clear_completed(has_completed: bool) -> Markup {
 
 fn toggle_all(toggle: bool) -> Markup {
     html! {
-        input
-            id="toggle-all"
-            class="toggle-all"
-            type="checkbox"
-            checked[toggle]
-            _="
-                on load set $toggleAll to me
-                on toggleAll debounced at 100ms
-                    fetch /toggle-all then
-                        if it === 'true' and my.checked === false then
-                            set my.checked to true
-                        else
-                            if my.checked === true and it === 'false' then set my.checked to false
-                        end
-                end
-                on click send toggle to <input.toggle/>
-            " {}
+        form method="post" action={ "/toggle-all-todos?done="(!toggle) } {
+            input
+                id="toggle-all"
+                class="toggle-all"
+                type="checkbox"
+                checked[toggle]
+                _="
+                    on click
+                        htmx.ajax('PATCH', `/toggle-all-todos?done=${my.checked}`, {target:'.todo-list', swap:'innerHTML'})
+                " {}
+            // the label style rely on sitting right after the checkbox
+            label for="toggle-all" {
+                "Mark all as complete"
+            }
+            // a checkbox can't submit on its own without scripting
+            noscript {
+                button type="submit" { "Mark all as " @if toggle { "active" } @else { "complete" } }
+            }
+        }
     }
 }
 
-pub fn filter_bar(filters: &[Filter]) -> Markup {
+pub fn filter_bar(selected: TodoFilter) -> Markup {
     html! {
-        ul class="filters" _="on load set $filter to me" {
-            @for filter in filters {
+        ul class="filters" {
+            @for filter in TodoFilter::ALL {
                 li {
+                    // the filter is part of the url, swap in the app as rendered
+                    // for that url, the response push it so back/forward keep working
                     a
-                        class={ @if filter.selected { "selected" } }
-                        href={ (filter.url) }
-                        _="on click add .selected to me"
-                        { (filter.name) }
+                        class={ @if filter == selected { "selected" } }
+                        href={ (filter.url()) }
+                        hx-get={ (filter.url()) }
+                        hx-target="#todoapp"
+                        hx-swap="outerHTML"
+                        { (filter) }
                 }
             }
         }
@@ -56,64 +69,63 @@ pub fn filter_bar(filters: &[Filter]) -> Markup {
 
 // blur event handle both keyup ESC and blur
 // where only blur should trigger update call while ESC is not
-pub fn edit_todo(todo: &Todo) -> Markup {
+// without scripting the post come from the edit page, its Referer don't tell the
+// filter anymore so it ride along in the action
+pub fn edit_todo(todo: &Todo, filter: TodoFilter) -> Markup {
     let value = if todo.editing { &todo.task } else { "" };
     html! {
-        input
-            class="edit"
-            name="task"
-            value={ (value) }
-            _="
-                on load
-                    my.focus()
-                on keyup[keyCode==27]
-                    set $keyup to 'esc'
-                    remove .editing from closest <li/>
-                on keyup[keyCode==13]
-                    set $keyup to 'enter'
-                    htmx.ajax('GET', `/update-todo?id=${my.parentNode.id.slice(5)}&task=${my.value}`, {target: closest <li/>, swap:'outerHTML'})
-                on blur debounced at 10ms
-                    if $keyup === 'enter'
-                        set $keyup to 'none'
-                    else if $keyup === 'esc'
-                        set $keyup to 'none'
-                    else
-                    htmx.ajax('GET', `/update-todo?id=${my.parentNode.id.slice(5)}&task=${my.value}`, {target: closest <li/>, swap:'outerHTML'})
-                end
-                send toggleMain to <section.todoapp/>
-                send toggleFooter to <section.todoapp/>
-            " {}
+        form
+            class="edit-form"
+            method="post"
+            action={ "/todos/"(todo.id)(filter.query()) }
+            hx-patch={ "/todos/"(todo.id) }
+            hx-target="closest <li/>"
+            hx-swap="outerHTML" {
+            input
+                class="edit"
+                name="task"
+                value={ (value) }
+                _="
+                    on load
+                        my.focus()
+                    on keyup[keyCode==27]
+                        set $keyup to 'esc'
+                        remove .editing from closest <li/>
+                    on keyup[keyCode==13]
+                        set $keyup to 'enter'
+                    on blur debounced at 10ms
+                        if $keyup === 'enter'
+                            set $keyup to 'none'
+                        else if $keyup === 'esc'
+                            set $keyup to 'none'
+                        else
+                        call (closest <form/>).requestSubmit()
+                    end
+                " {}
+        }
     }
 }
 
 fn todo_check(todo: &Todo) -> Markup {
     let toggle = todo.done == true;
     html! {
-        input
-            class="toggle"
-            type="checkbox"
-            checked[toggle]
-            hx-patch={ "/toggle-todo?id="(todo.id)"&done="(todo.done) }
-            hx-target="closest <li/>"
-            hx-swap="outerHTML"
-            _="
-            on htmx:afterRequest
-                send toggleAll to <input.toggle-all/>
-                send toggleClearCompleted to <footer.footer/>
-            on toggle
-                send toggleClearCompleted to <footer.footer/>
-                if $toggleAll.checked and my.checked === false
-                    my.click()
-                else if $toggleAll.checked === false and my.checked
-                    my.click()
-            " {}
+        form method="post" action={ "/todos/"(todo.id)"/toggle" } {
+            input
+                class="toggle"
+                type="checkbox"
+                checked[toggle]
+                hx-patch={ "/todos/"(todo.id)"/toggle" }
+                hx-target="closest <li/>"
+                hx-swap="outerHTML" {}
+            noscript {
+                button type="submit" { @if toggle { "Undo" } @else { "Done" } }
+            }
+        }
     }
 }
 
-pub fn todo_item(todo: &Todo, filter_name: &str) -> Markup {
-    let should_render = !todo.done && filter_name == "Active"
-        || todo.done && filter_name == "Completed"
-        || filter_name == "All";
+pub fn todo_item(todo: &Todo, filter: TodoFilter) -> Markup {
+    let should_render = filter.matches(todo);
     html! {
         @if should_render {
             li
@@ -122,246 +134,146 @@ pub fn todo_item(todo: &Todo, filter_name: &str) -> Markup {
                     "todo "
                     @if todo.done { "completed " }
                     @if todo.editing { "editing" }
-                }
-                _="on destroy my.querySelector('button').click()" {
+                } {
                 div class="view" {
                     (todo_check(todo))
                     label
                         hx-trigger="dblclick"
-                        hx-patch={ "/edit-todo?id="(todo.id) }
-                        hx-target="next input"
+                        hx-get={ "/todos/"(todo.id)"/edit" }
+                        hx-target="next form.edit-form"
                         hx-swap="outerHTML"
                         _="
                         on dblclick add .editing to the closest <li/>
                         on htmx:afterRequest
-                            set $el to my.parentNode.nextSibling
+                            set $el to the first <input.edit/> in the closest <li/>
                             set $el.selectionStart to $el.value.length
                         " { (todo.task) }
-                    button
-                        class="destroy"
-                        hx-delete={ "/remove-todo?id="(todo.id) }
-                        hx-trigger="click"
-                        hx-target="closest <li/>"
-                        hx-swap="outerHTML"
-                        _="
-                            on htmx:afterRequest debounced at 5ms
-                                send toggleMain to <section.todoapp/>
-                                send toggleFooter to <section.todoapp/>
-                                send focus to <input.new-todo/>
-                                if $todo
-                                    send toggleClearCompleted to <footer.footer/>
-                        " {}
+                    // double click need scripting, the edit page open the todo for editing
+                    noscript {
+                        a href={ "/todos/"(todo.id)"/edit"(filter.query()) } { "Edit" }
+                    }
+                    form method="post" action={ "/todos/"(todo.id)"/delete" } {
+                        button
+                            type="submit"
+                            class="destroy"
+                            hx-delete={ "/todos/"(todo.id) }
+                            hx-trigger="click"
+                            hx-target="closest <li/>"
+                            hx-swap="outerHTML" {}
+                    }
                 }
-                (edit_todo(todo))
+                (edit_todo(todo, filter))
             }
         }
     }
 }
 
-pub fn toggle_main(todos: &[Todo], checked: bool) -> Markup {
+fn toggle_main(todos: &[Todo], checked: bool) -> Markup {
     let has_length = todos.len() != 0;
     html! {
         @if has_length {
-            section
-                class="main" _="on load set $sectionMain to me" {
-                    { (toggle_all(checked)) }
-                    label for="toggle-all" {
-                        "Mark all as complete"
-                    }
-                }
+            section class="main" {
+                { (toggle_all(checked)) }
+            }
         }
     }
 }
 
-pub fn footer(todos: &[Todo], filters: &[Filter], has_completed: bool) -> Markup {
+fn todo_count(todos: &[Todo]) -> Markup {
+    let uncompleted_count = todos.iter().filter(|todo| !todo.done).count();
+    let plural = if uncompleted_count != 1 { "s" } else { "" };
+    html! {
+        strong { (uncompleted_count) " item" (plural) " left" }
+    }
+}
+
+fn footer(todos: &[Todo], filter: TodoFilter, has_completed: bool) -> Markup {
     let has_todos = todos.len() != 0;
     html! {
         @if has_todos {
-            footer
-                class="footer"
-                _="
-                    on load set $footerFooter to me
-                    on toggleClearCompleted debounced at 20ms
-                        if $clearCompleted === undefined
-                            htmx.ajax('GET', '/completed', {target:'.filters', swap:'afterend'})
-                        else
-                            // need to first set to undefined in case the fetch may return empty which
-                            // will indiscriminately leave it in incorrect state
-                            set $clearCompleted to undefined
-                            htmx.ajax('GET', '/completed', {target:'.clear-completed', swap:'outerHTML'})
-                    send toggleFooter to <section.todoapp/>
-                " {
-                    span
-                        class="todo-count"
-                        hx-trigger="load"
-                        _="
-                            on load send todoCount to me
-                            on todoCount debounced at 100ms
-                            fetch /update-counts then put the result into me
-                        " {}
-                    (filter_bar(filters))
-                    (clear_completed(has_completed))
-                }
+            footer class="footer" {
+                span class="todo-count" { (todo_count(todos)) }
+                (filter_bar(filter))
+                (clear_completed(has_completed))
+            }
         }
     }
 }
 
-pub fn todo_list(todos: &[Todo], filter_name: &str) -> Markup {
-    let has_todos = todos.len() != 0;
+// the main section and the footer come and go with the todos, they sit in slots
+// that are always on the page so a mutation can replace them with hx-swap-oob
+// in the same response instead of the page asking for each one after the fact
+pub fn main_slot(todos: &[Todo], checked: bool, oob: bool) -> Markup {
     html! {
-        @if has_todos {
-            ul
-                class="todo-list"
-                _="on load set $todo to me" {
-                @for todo in todos {
-                    { (todo_item(todo, filter_name)) }
-                }
+        div id="main-slot" hx-swap-oob=[oob.then_some("true")] {
+            (toggle_main(todos, checked))
+        }
+    }
+}
+
+pub fn footer_slot(todos: &[Todo], filter: TodoFilter, has_completed: bool, oob: bool) -> Markup {
+    html! {
+        div id="footer-slot" hx-swap-oob=[oob.then_some("true")] {
+            (footer(todos, filter, has_completed))
+        }
+    }
+}
+
+// always on the page, even empty, so new todos have somewhere to go
+fn todo_list(todos: &[Todo], filter: TodoFilter) -> Markup {
+    html! {
+        ul class="todo-list" {
+            @for todo in todos {
+                { (todo_item(todo, filter)) }
             }
         }
     }
 }
 
-fn todoapp(
-    filters: &[Filter],
-    todos: &[Todo],
-    checked: bool,
-    has_completed: bool,
-    filter_name: &str,
-) -> Markup {
+pub fn todoapp(todos: &[Todo], checked: bool, has_completed: bool, filter: TodoFilter) -> Markup {
     html! {
-        body {
-            section
-                class="todoapp"
-                _="
-                    on toggleMain debounced at 20ms
-                        // log 'toggleMain'
-                        if $sectionMain
-                            set $sectionMain to undefined
-                            htmx.ajax('GET', '/toggle-main', {target:'section.main', swap:'outerHTML'})
-                        else
-                            htmx.ajax('GET', '/toggle-main', {target:'.todo-list', swap:'beforebegin'})
-                        end
-                    on toggleFooter debounced at 20ms
-                        // log 'toggleFooter'
-                        if $footerFooter
-                            fetch /todo-json as json then
-                                if $todo.hasChildNodes() === false and it.length === 0
-                                    remove $footerFooter
-                                    set $footerFooter to undefined
-                                end
-                            // set-hash already update the hash on the server
-                            // this reassign the filter class selected base on user interaction
-                            // or location hash changes
-                            for filter in $filter.children
-                                if filter.textContent === 'All' and `${$initial}${$after}` === ''
-                                    add .selected to filter.firstChild
-                                else if filter.textContent !== `${$initial}${$after}`
-                                    remove .selected from filter.firstChild
-                                end
-                            end
-                            // update counts
-                            fetch /update-counts then put the result into <span.todo-count/>
-                        else
-                            htmx.ajax('GET', '/footer', {target:'.header', swap:'beforeend'})
-                        end
-                    on show wait 20ms
-                        log 'fetch show'
-                        // this is the DOM tree diffing of the todo-list, fetch only the needed
-                        // to render and remove accordingly base on route All/Active/Completed
-                        fetch /todo-json as json then
-                            if window.location.hash === '#/active'
-                                for todo in it
-                                    if todo.done
-                                        document.getElementById(`todo-${todo.id}`) then if it remove it end
-                                    else
-                                        document.getElementById(`todo-${todo.id}`) then
-                                            if it === null
-                                                htmx.ajax('GET', `/todo-item?id=${todo.id}`, {target:'.todo-list', swap:'beforeend'})
-                                            end
-                                    end
-                                end
-                            else if window.location.hash === '#/completed'
-                                for todo in it
-                                    if todo.done
-                                        document.getElementById(`todo-${todo.id}`) then
-                                            if it === null
-                                                htmx.ajax('GET', `/todo-item?id=${todo.id}`, {target:'.todo-list', swap:'beforeend'})
-                                            end
-                                    else
-                                        document.getElementById(`todo-${todo.id}`) then if it remove it end
-                                    end
-                                end
-                            else
-                                // loop through the JSON
-                                for todo in it
-                                    // check if the element exist in the current DOM, add if none
-                                    // placement is decided according to order if there's an element
-                                    // with higher than the current todo swap as 'beforebegin'
-                    
-                                    for el in $todo.children
-                                        if parseInt(el.id.slice(5)) > todo.id and document.getElementById(`todo-${todo.id}`) === null
-                                        htmx.ajax('GET', `/todo-item?id=${todo.id}`, {target: `#${el.id}`, swap:'beforebegin'})
-                                        end
-                                    end
-                
-                                    // do reverse lookup for lower than the current todo swap as 'afterend'
-                                    for el in Array.from($todo.children).reverse()
-                                        if parseInt(el.id.slice(5)) < todo.id and document.getElementById(`todo-${todo.id}`) === null
-                                        htmx.ajax('GET', `/todo-item?id=${todo.id}`, {target: `#${el.id}`, swap:'afterend'})
-                                        end
-                                    end
-                
-                                    // if todo is empty initially recursively add all of it
-                                    if $todo.children.length === 0
-                                        htmx.ajax('GET', `/todo-item?id=${todo.id}`, {target:'.todo-list', swap:'beforeend'})
-                                    end
+        section id="todoapp" class="todoapp" {
+            header class="header" {
+                h1 { "todos" }
+                // the server pick where the new todo go and send todoAdded once it
+                // is on the page, todoRemoved when one leave it, keep the focus here
+                form method="post" action="/add-todo" hx-post="/add-todo" hx-swap="none" {
+                    input
+                        id="add-todo"
+                        name="task"
+                        class="new-todo"
+                        placeholder="What needs to be done?"
+                        _="
+                            on load send focus to me
+                            on focus
+                                if $focus === undefined
+                                    my.focus()
+                                    set $isFocus to 'true'
                                 end
-                "
-                {
-                    header class="header" {
-                        h1 { "todos" }
-                        input
-                            id="add-todo"
-                            name="task"
-                            class="new-todo"
-                            placeholder="What needs to be done?"
-                            _="
-                                on load send focus to me
-                                on focus
-                                    if $focus === undefined
-                                        my.focus()
-                                        set $isFocus to 'true'
-                                    end
-                                on blur set $isFocus to undefined
-                                on keyup[keyCode==13]
-                                    if $todo
-                                        htmx.ajax('GET', `/add-todo?task=${my.value}`, {target:'.todo-list', swap:'beforeend'})
-                                        set my value to ''
-                                    else
-                                        htmx.ajax('GET', `/add-todo?task=${my.value}`, {target:'.header', swap:'beforeend'})
-                                        set my value to ''
-                                    end
-                                        send toggleMain to <section.todoapp/>
-                                        send toggleFooter to <section.todoapp/>
-                            " {}
-                    }
-                    { (toggle_main(todos, checked)) }
-                    { (todo_list(todos, filter_name))}
-                    { (footer(todos, filters, has_completed)) }
+                            on blur set $isFocus to undefined
+                            on todoAdded from body
+                                set my value to ''
+                                send focus to me
+                            on todoRemoved from body send focus to me
+                        " {}
                 }
+            }
+            { (main_slot(todos, checked, false)) }
+            { (todo_list(todos, filter)) }
+            { (footer_slot(todos, filter, has_completed, false)) }
         }
     }
 }
 
-fn header(page_title: &str) -> Markup {
+fn header(page_title: &str, assets: AssetSource) -> Markup {
     html! {
         (DOCTYPE)
         html lang="en" data-framework="htmx";
         head {
             meta charset="utf-8";
             title { (page_title) }
-            link rel="stylesheet" type="text/css" href="https://unpkg.com/todomvc-common@1.0.5/base.css";
-            link rel="stylesheet" type="text/css" href="https://unpkg.com/todomvc-app-css/index.css";
+            link rel="stylesheet" type="text/css" href=(assets.url(&BASE_CSS));
+            link rel="stylesheet" type="text/css" href=(assets.url(&APP_CSS));
         }
     }
 }
@@ -373,17 +285,15 @@ fn info() -> Markup {
             _="
                 on load debounced at 10ms
                     call startMeUp()
-                    hashCache()
             " {
             p { "Double-click to edit a todo" }
             p { "Created by " a href="http://github.com/syarul/" { "syarul" } }
             p { "Part of " a href="http://todomvc.com" { "TodoMVC" } }
-            img src="https://htmx.org/img/createdwith.jpeg" width="250" height="auto" {}
         }
     }
 }
 
-fn scripts() -> Markup {
+fn scripts(assets: AssetSource) -> Markup {
     let start_me_up = PreEscaped(
         r#"
         def startMeUp()
@@ -405,45 +315,45 @@ fn scripts() -> Markup {
                                     |___/                           |___/ 
                             by http://github.com/syarul/"
         end
-        def hashCache()
-            // this is done to get current location hash then update todo-list and footer
-            set $initial to window.location.hash.slice(2).charAt(0).toUpperCase()
-            set $after to window.location.hash.slice(3)
-            fetch `/set-hash?name=${$initial}${$after}` then
-              send show to <section.todoapp/>
-              send toggleFooter to <section.todoapp/>
-        end
-        // this to handle popstate event such as back/forward button
-        // where it will automatically calling hashCache _hyperscript function
-        js
-            window.addEventListener('popstate', function(){
-                hashCache();
-            });
-        end
     "#,
     );
     html! {
-        script src="https://unpkg.com/todomvc-common@1.0.5/base.js" {}
-        script src="https://unpkg.com/htmx.org@1.9.10" {}
-        script src="https://unpkg.com/hyperscript.org/dist/_hyperscript.js" {}
+        script src=(assets.url(&BASE_JS)) {}
+        script src=(assets.url(&HTMX_JS)) {}
+        script src=(assets.url(&HYPERSCRIPT_JS)) {}
         script type="text/hyperscript" { (start_me_up) }
     }
 }
 
 pub fn page(
     title: &str,
-    filters: &[Filter],
+    assets: AssetSource,
     todos: &[Todo],
     checked: bool,
     has_completed: bool,
-    filter_name: &str,
+    filter: TodoFilter,
 ) -> Markup {
     html! {
-        (header(title))
+        (header(title, assets))
         body {
-            (todoapp(filters, todos, checked, has_completed, filter_name))
+            (todoapp(todos, checked, has_completed, filter))
+            // the server retarget its error fragments here, htmx leave an error
+            // response out of the page unless told to swap it, only those are let
+            // through so a bare 405 or 503 never blank the element that asked
+            div
+                id="errors"
+                class="errors"
+                role="alert"
+                _="
+                    on htmx:beforeSwap from body
+                        if event.detail.xhr.status >= 400
+                            and event.detail.xhr.getResponseHeader('HX-Retarget') === '#errors'
+                            set event.detail.shouldSwap to true
+                            set event.detail.isError to false
+                        end
+                " {}
             (info())
-            (scripts())
+            (scripts(assets))
         }
     }
 }
@@ -0,0 +1,444 @@
+use crate::assets;
+use crate::compression;
+use crate::config::Config;
+use crate::error::AppError;
+use crate::fragments::{edit_todo, footer_slot, main_slot, page, todo_item, todoapp};
+use crate::metrics::Metrics;
+use crate::router::Params;
+use crate::session::Session;
+use crate::static_files;
+use crate::store::TodoStore;
+use crate::{
+    build_response, build_str_struct, def_checked, extract_query_param, has_complete_task,
+    response, with_hx, Hx, TodoFilter,
+};
+use astra::{Body, Request, Response, ResponseBuilder};
+use http::Uri;
+use maud::{html, Markup, PreEscaped};
+use serde_json::json;
+use std::{collections::HashMap, io::Read, str::FromStr, sync::MutexGuard, time::Instant};
+use url::form_urlencoded::parse;
+
+// what every handler get to work with, the request, the segments captured
+// from the route pattern, the visitor's session, the server settings and
+// the metrics to report into
+pub struct Ctx<'a> {
+    pub req: Request,
+    pub params: Params,
+    pub session: &'a Session,
+    pub config: &'a Config,
+    pub metrics: &'a Metrics,
+}
+
+// most handlers work on the visitor's todos and need their session, the rest
+// answer the same for everyone (assets, static files, learn.json) and get none,
+// so a response a shared cache may keep never carry anyone's cookie
+#[derive(Clone, Copy)]
+pub enum Handler {
+    Session(fn(&mut Ctx) -> Result<Response, AppError>),
+    Public(fn(&Request, &Config) -> Result<Response, AppError>),
+}
+
+impl Ctx<'_> {
+    // acquire the lock to access and modify the todo store,
+    // if poisoned, force to allow access regardless, can be approach in different ways,
+    // the time spent waiting for it go to the metrics
+    fn todos(&self) -> MutexGuard<'_, Box<dyn TodoStore>> {
+        let waiting = Instant::now();
+        let todos = self.session.todos.lock().unwrap_or_else(|e| e.into_inner());
+        self.metrics.observe_lock_wait(waiting.elapsed());
+        todos
+    }
+
+    fn query_param(&self, param_name: &str) -> Option<String> {
+        self.req
+            .uri()
+            .query()
+            .and_then(|query| extract_query_param(query, param_name))
+    }
+
+    // read an application/x-www-form-urlencoded body, the task text travel here
+    // instead of the query so any character round trip and it stay out of the logs,
+    // the error tell which status to hand back
+    fn form(&mut self) -> Result<Form, AppError> {
+        let content_type = self
+            .req
+            .headers()
+            .get("Content-Type")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if !content_type.starts_with("application/x-www-form-urlencoded") {
+            return Err(AppError::UnsupportedMediaType(
+                "send the form as application/x-www-form-urlencoded".to_string(),
+            ));
+        }
+
+        // read one byte past the limit to tell a full body from an oversized one
+        let mut bytes = Vec::new();
+        let read = self
+            .req
+            .body_mut()
+            .reader()
+            .take(MAX_FORM_BYTES + 1)
+            .read_to_end(&mut bytes);
+        if read.is_err() {
+            return Err(AppError::BadRequest(
+                "the form could not be read".to_string(),
+            ));
+        }
+        if bytes.len() as u64 > MAX_FORM_BYTES {
+            return Err(AppError::PayloadTooLarge(format!(
+                "the form can't be over {} bytes",
+                MAX_FORM_BYTES
+            )));
+        }
+        Ok(Form(parse(&bytes).into_owned().collect()))
+    }
+
+    // the filter the visitor is looking at, a page request carry it in its own path
+    // while htmx tell us the page it was sent from in HX-Current-URL, a plain
+    // form post only has the Referer, the edit link and form carry it in the
+    // query since the edit page is no filter page
+    fn filter(&self) -> TodoFilter {
+        let uri = self.req.uri();
+        if let Some(filter) = filter_of(uri.path(), uri.query()) {
+            return filter;
+        }
+        let headers = self.req.headers();
+        headers
+            .get("HX-Current-URL")
+            .or_else(|| headers.get("Referer"))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|url| url.parse::<Uri>().ok())
+            .and_then(|uri| filter_of(uri.path(), uri.query()))
+            .unwrap_or_default()
+    }
+
+    // htmx ask for the piece it is going to swap, anything else, a browser following
+    // a link or a bookmark, or htmx restoring a page missing from its history cache,
+    // get the whole page so every url can be opened directly
+    fn is_htmx(&self) -> bool {
+        let headers = self.req.headers();
+        headers.contains_key("HX-Request") && !headers.contains_key("HX-History-Restore-Request")
+    }
+
+    // the id of the element htmx is swapping into, if it has one
+    fn hx_target(&self) -> Option<&str> {
+        self.req
+            .headers()
+            .get("HX-Target")
+            .and_then(|value| value.to_str().ok())
+    }
+
+    // a path segment parsed to the type the handler want, None when it is
+    // missing or malformed so the handler can answer 400
+    fn param<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.params.get(name).and_then(|value| value.parse().ok())
+    }
+
+    // the id in the path is not something we could ever have handed out
+    fn todo_id(&self) -> Result<u32, AppError> {
+        self.param("id")
+            .ok_or_else(|| AppError::BadRequest("the todo id must be a number".to_string()))
+    }
+}
+
+// a task is a line of text, anything bigger than this is not a todo
+const MAX_FORM_BYTES: u64 = 16 * 1024;
+
+struct Form(HashMap<String, String>);
+
+impl Form {
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(|value| value.as_str())
+    }
+}
+
+// the id is well formed but the todo is gone, or never belonged to this session
+fn not_found(id: u32) -> AppError {
+    AppError::NotFound(format!("no todo with id {}", id))
+}
+
+// a filter page's own path, or the filter query of the edit page and its form
+fn filter_of(path: &str, query: Option<&str>) -> Option<TodoFilter> {
+    TodoFilter::from_path(path).or_else(|| {
+        query
+            .and_then(|query| extract_query_param(query, "filter"))
+            .and_then(|filter| filter.parse().ok())
+    })
+}
+
+// the todo was read under the lock yet the store had nothing to update,
+// someone else got to it first
+fn conflict(id: u32) -> AppError {
+    AppError::Conflict(format!("todo {} changed while it was being saved", id))
+}
+
+// a mutation answer htmx with what it changed plus the main section and the footer
+// swapped out of band, so the list, toggle all, counter and clear completed
+// are all up to date after a single round trip, a plain form post is sent back
+// to the page it came from
+fn changed(
+    ctx: &Ctx,
+    todos: &dyn TodoStore,
+    mk: Markup,
+    hx: Vec<Hx>,
+) -> Result<Response, AppError> {
+    let filter = ctx.filter();
+    if !ctx.is_htmx() {
+        return Ok(see_other(filter.url()));
+    }
+    let list = todos.list()?;
+    let struct_response = html! {
+        (mk)
+        (main_slot(&list, def_checked(todos)?, true))
+        (footer_slot(&list, filter, has_complete_task(todos)?, true))
+    };
+    Ok(with_hx(response(200, struct_response, None), hx))
+}
+
+// 303 so the browser follow up with a GET and a reload never post the form again
+fn see_other(url: &str) -> Response {
+    build_response(
+        ResponseBuilder::new().status(303).header("Location", url),
+        Body::empty(),
+    )
+}
+
+// the same url answer htmx and a browser differently, and htmx differently again
+// depending on the element it target, tell caches about both
+fn vary(mut res: Response) -> Response {
+    if let Ok(header_value) = "HX-Request, HX-Target".parse() {
+        res.headers_mut().insert("Vary", header_value);
+    }
+    res
+}
+
+// the whole app as a page, with the todo being edited open if there is one
+fn full_page(
+    ctx: &Ctx,
+    todos: &dyn TodoStore,
+    filter: TodoFilter,
+    editing: Option<u32>,
+) -> Result<Response, AppError> {
+    let mut list = todos.list()?;
+    for todo in &mut list {
+        todo.editing = Some(todo.id) == editing;
+    }
+    let mk = page(
+        &ctx.config.title,
+        ctx.config.assets,
+        &list,
+        def_checked(todos)?,
+        has_complete_task(todos)?,
+        filter,
+    );
+    Ok(vary(response(200, mk, None)))
+}
+
+pub fn index(ctx: &mut Ctx) -> Result<Response, AppError> {
+    let filter = ctx.filter();
+    let todos = ctx.todos();
+    // a filter link swap the app in place, record the page it now show in the history
+    if ctx.is_htmx() && ctx.hx_target() == Some("todoapp") {
+        let mk = todoapp(
+            &todos.list()?,
+            def_checked(todos.as_ref())?,
+            has_complete_task(todos.as_ref())?,
+            filter,
+        );
+        let res = with_hx(response(200, mk, None), vec![Hx::PushUrl(filter.url())]);
+        return Ok(vary(res));
+    }
+    full_page(ctx, todos.as_ref(), filter, None)
+}
+
+pub fn learn_json(_req: &Request, _config: &Config) -> Result<Response, AppError> {
+    let json_str = PreEscaped(serde_json::to_string(&json!({}))?);
+    Ok(response(200, json_str, Some("application/json")))
+}
+
+pub fn toggle_all(ctx: &mut Ctx) -> Result<Response, AppError> {
+    let done = ctx
+        .query_param("done")
+        .and_then(|done| done.parse::<bool>().ok());
+    if let Some(done) = done {
+        let mut todos = ctx.todos();
+        todos.toggle_all(done)?;
+        let filter = ctx.filter();
+        let struct_response = html! {
+            @for todo in &todos.list()? {
+                (todo_item(todo, filter))
+            }
+        };
+        return changed(ctx, todos.as_ref(), struct_response, vec![]);
+    }
+    Err(AppError::BadRequest(
+        "done must be true or false".to_string(),
+    ))
+}
+
+pub fn remove_completed(ctx: &mut Ctx) -> Result<Response, AppError> {
+    let mut todos = ctx.todos();
+    todos.clear_completed()?;
+    let filter = ctx.filter();
+    let struct_response = html! {
+        @for todo in &todos.list()? {
+            (todo_item(todo, filter))
+        }
+    };
+    changed(ctx, todos.as_ref(), struct_response, vec![])
+}
+
+pub fn add_todo(ctx: &mut Ctx) -> Result<Response, AppError> {
+    let form = ctx.form()?;
+    let filter = ctx.filter();
+    let todo_task = form.get("task");
+    if let Some(task) = todo_task {
+        let task_trim = task.trim();
+        if !task_trim.is_empty() {
+            let mut todos = ctx.todos();
+            let todo = todos.insert(task_trim.to_string())?;
+            let struct_response = build_str_struct(|todo| todo_item(todo, filter), &todo);
+            // the new todo go at the end of the list, unless the current filter hide it
+            let swap = if filter.matches(&todo) {
+                "beforeend"
+            } else {
+                "none"
+            };
+            let hx = vec![
+                Hx::Retarget(".todo-list"),
+                Hx::Reswap(swap),
+                Hx::TriggerAfterSwap("todoAdded"),
+            ];
+            return changed(ctx, todos.as_ref(), struct_response, hx);
+        } else if !ctx.is_htmx() {
+            return Ok(see_other(filter.url()));
+        } else {
+            return Ok(response(200, PreEscaped(String::new()), None));
+        }
+    }
+    Err(AppError::BadRequest(
+        "the task field is missing".to_string(),
+    ))
+}
+
+pub fn toggle_todo(ctx: &mut Ctx) -> Result<Response, AppError> {
+    let todo_id = ctx.todo_id()?;
+    let mut todos = ctx.todos();
+    let mut todo = todos.get(todo_id)?.ok_or_else(|| not_found(todo_id))?;
+    todo.done = !todo.done;
+    if !todos.update(&todo)? {
+        return Err(conflict(todo_id));
+    }
+    let filter = ctx.filter();
+    let struct_response = build_str_struct(|todo| todo_item(todo, filter), &todo);
+    // the todo left the list when the filter no longer match it
+    let hx = if filter.matches(&todo) {
+        vec![]
+    } else {
+        vec![Hx::Trigger("todoRemoved")]
+    };
+    changed(ctx, todos.as_ref(), struct_response, hx)
+}
+
+pub fn edit_todo_fragment(ctx: &mut Ctx) -> Result<Response, AppError> {
+    let todo_id = ctx.todo_id()?;
+    let todos = ctx.todos();
+    let mut todo = todos.get(todo_id)?.ok_or_else(|| not_found(todo_id))?;
+    if !ctx.is_htmx() {
+        return full_page(ctx, todos.as_ref(), ctx.filter(), Some(todo_id));
+    }
+    // the store hand out a copy, only flag this one as editing
+    todo.editing = true;
+    let filter = ctx.filter();
+    let struct_response = build_str_struct(|todo| edit_todo(todo, filter), &todo);
+    Ok(vary(response(200, struct_response, None)))
+}
+
+pub fn update_todo(ctx: &mut Ctx) -> Result<Response, AppError> {
+    let todo_id = ctx.todo_id()?;
+    let form = ctx.form()?;
+    let task = form.get("task").unwrap_or_default().to_string();
+    let mut todos = ctx.todos();
+    let mut todo = todos.get(todo_id)?.ok_or_else(|| not_found(todo_id))?;
+    if task.trim().is_empty() {
+        // behave same as remove if user send empty task
+        todos.remove(todo_id)?;
+        return changed(ctx, todos.as_ref(), PreEscaped(String::new()), vec![]);
+    }
+    todo.task = task;
+    if !todos.update(&todo)? {
+        return Err(conflict(todo_id));
+    }
+    let filter = ctx.filter();
+    let struct_response = build_str_struct(|todo| todo_item(todo, filter), &todo);
+    changed(ctx, todos.as_ref(), struct_response, vec![])
+}
+
+pub fn remove_todo(ctx: &mut Ctx) -> Result<Response, AppError> {
+    let todo_id = ctx.todo_id()?;
+    let mut todos = ctx.todos();
+    if !todos.remove(todo_id)? {
+        return Err(not_found(todo_id));
+    }
+    let hx = vec![Hx::Trigger("todoRemoved")];
+    changed(ctx, todos.as_ref(), PreEscaped(String::new()), hx)
+}
+
+pub fn todo_json(ctx: &mut Ctx) -> Result<Response, AppError> {
+    Ok(response(
+        200,
+        PreEscaped(serde_json::to_string(&ctx.todos().list()?)?),
+        Some("application/json"),
+    ))
+}
+
+pub fn todo_item_fragment(ctx: &mut Ctx) -> Result<Response, AppError> {
+    let todo_id = ctx.todo_id()?;
+    let todos = ctx.todos();
+    let todo = todos.get(todo_id)?.ok_or_else(|| not_found(todo_id))?;
+    let filter = ctx.filter();
+    if !ctx.is_htmx() {
+        return full_page(ctx, todos.as_ref(), filter, None);
+    }
+    let struct_response = build_str_struct(|todo| todo_item(todo, filter), &todo);
+    Ok(vary(response(200, struct_response, None)))
+}
+
+// files under the static directory, e.g. axe-core for the cypress tests, the
+// request path is decoded and looked up below it
+pub fn static_file(req: &Request, config: &Config) -> Result<Response, AppError> {
+    let relative = req.uri().path().trim_start_matches('/');
+    static_files::serve(&config.static_dir, relative, req)?
+        .ok_or_else(|| AppError::NotFound(format!("no file at /{}", relative)))
+}
+
+// the assets compiled into the binary, their path carry the version so browsers can keep them,
+// the compressed copies were made at build time so pick the one the client accept
+pub fn asset(req: &Request, _config: &Config) -> Result<Response, AppError> {
+    let Some((asset, embedded)) =
+        assets::find(req.uri().path()).and_then(|asset| Some((asset, asset.embedded.as_ref()?)))
+    else {
+        return Err(AppError::NotFound(format!(
+            "{} is not built in",
+            req.uri().path()
+        )));
+    };
+    let encoding = compression::accepted(req);
+    let body = embedded.encoded(encoding);
+    let mut res = build_response(
+        ResponseBuilder::new()
+            .header("Content-Type", asset.content_type)
+            .header("Cache-Control", "public, max-age=31536000, immutable")
+            .header("Content-Length", body.len())
+            .status(200),
+        Body::new(body),
+    );
+    if let Some(encoding) = encoding {
+        if let Ok(header_value) = encoding.name().parse() {
+            res.headers_mut().insert("Content-Encoding", header_value);
+        }
+    }
+    Ok(res)
+}
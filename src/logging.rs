@@ -0,0 +1,142 @@
+use crate::config::{LogFormat, LogLevel};
+use serde_json::{Map, Value};
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    sync::OnceLock,
+};
+use time::OffsetDateTime;
+
+// one line per event on stderr, either `key=value` text for people or a json
+// object per line for log collectors, the level set in the config decide what is kept
+struct Logger {
+    level: LogLevel,
+    format: LogFormat,
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+thread_local! {
+    // astra handle a request start to finish on one worker thread, so anything
+    // logged while it run is tagged with its request id
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn init(level: LogLevel, format: LogFormat) {
+    let _ = LOGGER.set(Logger { level, format });
+}
+
+fn logger() -> &'static Logger {
+    LOGGER.get_or_init(|| Logger {
+        level: LogLevel::Info,
+        format: LogFormat::Plain,
+    })
+}
+
+pub fn enabled(level: LogLevel) -> bool {
+    level <= logger().level
+}
+
+// tag every event on this thread with the request id until the guard is dropped
+pub struct RequestScope;
+
+impl Drop for RequestScope {
+    fn drop(&mut self) {
+        REQUEST_ID.with(|id| id.borrow_mut().take());
+    }
+}
+
+pub fn request_scope(request_id: &str) -> RequestScope {
+    REQUEST_ID.with(|id| *id.borrow_mut() = Some(request_id.to_string()));
+    RequestScope
+}
+
+fn timestamp() -> String {
+    let now = OffsetDateTime::now_utc();
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second(),
+        now.millisecond(),
+    )
+}
+
+fn level_name(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Error => "error",
+        LogLevel::Warn => "warn",
+        LogLevel::Info => "info",
+        LogLevel::Debug => "debug",
+    }
+}
+
+// bare words stay bare, anything with spaces, quotes or = is quoted
+fn plain_value(value: &Value) -> String {
+    match value {
+        Value::String(text)
+            if !text.is_empty()
+                && !text
+                    .chars()
+                    .any(|c| c.is_whitespace() || c == '"' || c == '=') =>
+        {
+            text.clone()
+        }
+        Value::Null => "-".to_string(),
+        other => other.to_string(),
+    }
+}
+
+pub fn event(level: LogLevel, message: &str, fields: &[(&str, Value)]) {
+    if !enabled(level) {
+        return;
+    }
+    let request_id = REQUEST_ID.with(|id| id.borrow().clone());
+    let line = match logger().format {
+        LogFormat::Plain => {
+            let mut line = format!(
+                "{} {:5} {}",
+                timestamp(),
+                level_name(level).to_uppercase(),
+                message
+            );
+            if let Some(request_id) = &request_id {
+                line.push_str(&format!(" request_id={}", request_id));
+            }
+            for (key, value) in fields {
+                line.push_str(&format!(" {}={}", key, plain_value(value)));
+            }
+            line
+        }
+        LogFormat::Json => {
+            let mut object = Map::new();
+            object.insert("ts".to_string(), Value::from(timestamp()));
+            object.insert("level".to_string(), Value::from(level_name(level)));
+            object.insert("msg".to_string(), Value::from(message));
+            if let Some(request_id) = request_id {
+                object.insert("request_id".to_string(), Value::from(request_id));
+            }
+            for (key, value) in fields {
+                object.insert(key.to_string(), value.clone());
+            }
+            Value::Object(object).to_string()
+        }
+    };
+    // one write per line so lines from different threads never interleave
+    let _ = writeln!(io::stderr().lock(), "{}", line);
+}
+
+pub fn error(message: &str, fields: &[(&str, Value)]) {
+    event(LogLevel::Error, message, fields);
+}
+
+pub fn warn(message: &str, fields: &[(&str, Value)]) {
+    event(LogLevel::Warn, message, fields);
+}
+
+pub fn info(message: &str, fields: &[(&str, Value)]) {
+    event(LogLevel::Info, message, fields);
+}
@@ -1,30 +1,54 @@
+mod assets;
+mod compression;
+mod config;
+mod error;
 mod fragments;
+mod handlers;
+mod logging;
+mod metrics;
+mod router;
+mod session;
+mod shutdown;
+mod static_files;
+mod store;
 
 // extern crate maud
 pub use maud::*;
 
+use assets::AssetSource;
 use astra::{Body, ConnectionInfo, Request, Response, ResponseBuilder, Server};
-use chrono::DateTime;
-use fragments::{clear_completed, edit_todo, footer, page, todo_item, todo_list, toggle_main};
-use rand::distributions::Alphanumeric;
+use config::{Config, Storage};
+use cookie::Key;
+use error::AppError;
+use handlers::{Ctx, Handler};
+use metrics::Metrics;
 use rand::{thread_rng, Rng};
-use serde::Serialize;
-use serde_json::json;
+use router::{Route, Router};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use session::{session_hash, spawn_sweeper, SessionCookie, Sessions};
+use shutdown::{
+    Shutdown, EXIT_DRAIN_TIMEOUT, EXIT_FLUSH_FAILED, EXIT_OK, EXIT_SERVE_FAILED,
+    EXIT_STARTUP_FAILED,
+};
+use std::str::FromStr;
 use std::{
-    fmt::Debug,
-    fs::read_to_string,
+    fmt::{self, Debug},
+    io, process,
     sync::{
         atomic::{AtomicU32, Ordering},
-        Arc,
-        Mutex,
-        MutexGuard,
-        RwLock, //, PoisonError
+        mpsc,
+        Arc, //, PoisonError
     },
-    time::SystemTime,
+    thread,
+    time::Instant,
 };
+#[cfg(feature = "sqlite")]
+use store::SqliteBackend;
+use store::{Backend, EventLogBackend, JsonBackend, MemoryBackend, StoreResult, TodoStore};
 use url::form_urlencoded::parse;
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 struct Todo {
     id: u32,
     task: String,
@@ -36,7 +60,7 @@ impl Todo {
     // helper method to create a new instance with a calculated ID
     // the counter can only go up, which good enough for in-memory indexing
     // if we storing the data elsewhere this might not be the case anymore
-    fn new_id(task: String, done: bool, editing: bool, counter: &Arc<AtomicU32>) -> Todo {
+    fn new_id(task: String, done: bool, editing: bool, counter: &AtomicU32) -> Todo {
         let id = counter.fetch_add(1, Ordering::Relaxed); // increment the counter for the next ID
         Todo {
             id: id.into(),
@@ -47,35 +71,82 @@ impl Todo {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub struct Filter {
-    url: &'static str,
-    name: &'static str,
-    selected: bool,
+// the filters shown in the footer, each one is a page of its own, for a new
+// filter add the variant to ALL and the compiler point at every arm it need
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum TodoFilter {
+    #[default]
+    All,
+    Active,
+    Completed,
 }
 
-// use trait to make generic on implementation
-trait UpdateSelected {
-    type Item;
-    fn update_selected_by_property(
-        value: String,
-        filters: &Arc<RwLock<Vec<Self::Item>>>,
-        property: fn(&Self::Item) -> &str,
-    );
-}
+impl TodoFilter {
+    // in the order they appear in the footer
+    const ALL: [TodoFilter; 3] = [TodoFilter::All, TodoFilter::Active, TodoFilter::Completed];
+
+    // the url segment after the leading slash, the default filter sit at the root
+    fn segment(self) -> &'static str {
+        match self {
+            TodoFilter::All => "",
+            TodoFilter::Active => "active",
+            TodoFilter::Completed => "completed",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            TodoFilter::All => "All",
+            TodoFilter::Active => "Active",
+            TodoFilter::Completed => "Completed",
+        }
+    }
+
+    fn matches(self, todo: &Todo) -> bool {
+        match self {
+            TodoFilter::All => true,
+            TodoFilter::Active => !todo.done,
+            TodoFilter::Completed => todo.done,
+        }
+    }
 
-impl UpdateSelected for Filter {
-    type Item = Filter;
-    fn update_selected_by_property(
-        value: String,
-        filters: &Arc<RwLock<Vec<Filter>>>,
-        property: fn(&Filter) -> &str,
-    ) {
-        let mut filters_write = filters.write().unwrap();
-        for filter in &mut *filters_write {
-            filter.selected = property(filter) == value;
+    fn url(self) -> &'static str {
+        match self {
+            TodoFilter::All => "/",
+            TodoFilter::Active => "/active",
+            TodoFilter::Completed => "/completed",
         }
     }
+
+    // carry the filter to a url that is not a page, nothing for All
+    fn query(self) -> String {
+        match self {
+            TodoFilter::All => String::new(),
+            filter => format!("?filter={}", filter.segment()),
+        }
+    }
+
+    // map a page url to the filter it show, None for anything that is not a page
+    fn from_path(path: &str) -> Option<TodoFilter> {
+        path.strip_prefix('/')?.parse().ok()
+    }
+}
+
+impl FromStr for TodoFilter {
+    type Err = ();
+
+    fn from_str(segment: &str) -> Result<TodoFilter, ()> {
+        TodoFilter::ALL
+            .into_iter()
+            .find(|filter| filter.segment() == segment)
+            .ok_or(())
+    }
+}
+
+impl fmt::Display for TodoFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
 }
 
 // utility function to extract a parameter from the query string
@@ -99,10 +170,57 @@ fn response(status: u16, mk: PreEscaped<String>, content_type: Option<&str>) ->
         response_builder.header("Content-Type", "text/html; charset=utf-8")
     };
 
-    response_builder
-        .status(status)
-        .body(Body::new(mk_str))
-        .unwrap()
+    build_response(
+        response_builder
+            .status(status)
+            .header("Content-Length", mk_str.len()),
+        Body::new(mk_str),
+    )
+}
+
+// the builder only fail on a status or a header value it can't represent, a bug
+// on our side, answer a bare 500 rather than take the worker thread down with it
+fn build_response(builder: ResponseBuilder, body: Body) -> Response {
+    builder.body(body).unwrap_or_else(|e| {
+        logging::error(
+            "building the response failed",
+            &[("error", Value::from(e.to_string()))],
+        );
+        let mut res = Response::new(Body::empty());
+        if let Ok(status) = 500.try_into() {
+            *res.status_mut() = status;
+        }
+        res
+    })
+}
+
+// htmx response headers, let a handler tell the page what to do with the response
+// instead of the page chaining requests after the fact
+enum Hx {
+    // fire the event on the element that made the request, it bubble up to body
+    Trigger(&'static str),
+    TriggerAfterSwap(&'static str),
+    // swap into this css selector instead of the request's hx-target
+    Retarget(&'static str),
+    Reswap(&'static str),
+    PushUrl(&'static str),
+}
+
+fn with_hx(mut res: Response, hx: Vec<Hx>) -> Response {
+    for header in hx {
+        let (name, value) = match header {
+            Hx::Trigger(event) => ("HX-Trigger", event),
+            Hx::TriggerAfterSwap(event) => ("HX-Trigger-After-Swap", event),
+            Hx::Retarget(selector) => ("HX-Retarget", selector),
+            Hx::Reswap(swap) => ("HX-Reswap", swap),
+            Hx::PushUrl(url) => ("HX-Push-Url", url),
+        };
+        // append so several events on the same header all reach htmx
+        if let Ok(header_value) = value.parse() {
+            res.headers_mut().append(name, header_value);
+        }
+    }
+    res
 }
 
 // use generic so we can use this for different template
@@ -115,376 +233,516 @@ where
     mk
 }
 
-fn count_not_done(todos: &MutexGuard<'_, Vec<Todo>>) -> usize {
-    todos.iter().filter(|&todo| !todo.done).count()
+fn count_not_done(todos: &dyn TodoStore) -> StoreResult<usize> {
+    Ok(todos.counts()?.active())
 }
 
-fn def_checked(todos: &MutexGuard<'_, Vec<Todo>>) -> bool {
-    let uncompleted_count = count_not_done(todos);
-    let default_checked = uncompleted_count == 0 && !todos.is_empty();
-    default_checked
+fn def_checked(todos: &dyn TodoStore) -> StoreResult<bool> {
+    let uncompleted_count = count_not_done(todos)?;
+    Ok(uncompleted_count == 0 && todos.counts()?.total != 0)
 }
 
-fn has_complete_task(todos: &MutexGuard<'_, Vec<Todo>>) -> bool {
-    for todo in todos.iter() {
-        if todo.done {
-            return true;
-        }
-    }
-    false
+fn has_complete_task(todos: &dyn TodoStore) -> StoreResult<bool> {
+    Ok(todos.counts()?.completed != 0)
 }
 
-fn selected_filter(filters: Arc<RwLock<Vec<Filter>>>) -> String {
-    let filters_read = filters.read().unwrap();
-    for filter in filters_read.iter() {
-        if filter.selected {
-            return filter.name.to_string();
-        }
-    }
-    "All".to_string()
+// state shared by every server worker thread
+struct App {
+    sessions: Arc<Sessions>,
+    session_cookie: SessionCookie,
+    router: Router<Handler>,
+    config: Config,
+    shutdown: Shutdown,
+    metrics: Metrics,
 }
 
-fn update_counts(todos: &MutexGuard<'_, Vec<Todo>>) -> String {
-    let uncompleted_count = count_not_done(todos);
-    let plural = if uncompleted_count != 1 { "s" } else { "" };
+// why main stopped waiting
+enum Stop {
+    Signal,
+    ServerExited(io::Result<()>),
+}
 
-    format!("<strong>{} item{} left</strong>", uncompleted_count, plural)
+// every path the app answer, reads are GET and anything that change the todos
+// need the matching verb so a prefetch or a crawler never modify state
+fn routes() -> Router<Handler> {
+    let router = TodoFilter::ALL
+        .into_iter()
+        .fold(Router::<Handler>::new(), |router, filter| {
+            router.get(filter.url(), Handler::Session(handlers::index))
+        });
+    // only the assets compiled in get a route, the others are loaded from the cdn
+    let router = assets::ASSETS
+        .into_iter()
+        .filter(|asset| asset.embedded.is_some())
+        .fold(router, |router, asset| {
+            router.get(asset.path, Handler::Public(handlers::asset))
+        });
+    router
+        .get("/learn.json", Handler::Public(handlers::learn_json))
+        .get("/todo-json", Handler::Session(handlers::todo_json))
+        .get(
+            "/node_modules/{*path}",
+            Handler::Public(handlers::static_file),
+        )
+        .post("/add-todo", Handler::Session(handlers::add_todo))
+        .patch("/toggle-all-todos", Handler::Session(handlers::toggle_all))
+        .delete(
+            "/clear-completed",
+            Handler::Session(handlers::remove_completed),
+        )
+        // plain html forms only know GET and POST, these let the app work without scripting
+        .post("/toggle-all-todos", Handler::Session(handlers::toggle_all))
+        .post(
+            "/clear-completed",
+            Handler::Session(handlers::remove_completed),
+        )
+        .post("/todos/{id}", Handler::Session(handlers::update_todo))
+        .post(
+            "/todos/{id}/toggle",
+            Handler::Session(handlers::toggle_todo),
+        )
+        .post(
+            "/todos/{id}/delete",
+            Handler::Session(handlers::remove_todo),
+        )
+        .get(
+            "/todos/{id}",
+            Handler::Session(handlers::todo_item_fragment),
+        )
+        .patch("/todos/{id}", Handler::Session(handlers::update_todo))
+        .delete("/todos/{id}", Handler::Session(handlers::remove_todo))
+        .get(
+            "/todos/{id}/edit",
+            Handler::Session(handlers::edit_todo_fragment),
+        )
+        .patch(
+            "/todos/{id}/toggle",
+            Handler::Session(handlers::toggle_todo),
+        )
 }
 
-fn handle_request(
-    _req: Request,
-    _info: ConnectionInfo,
-    id_counter: Arc<AtomicU32>,
-    todos: Arc<Mutex<Vec<Todo>>>,
-    filters: Arc<RwLock<Vec<Filter>>>,
-) -> Response {
-    // acquire the lock to access and modify the todos vector,
-    // if poisoned, force to allow access regardless, can be approach in different ways
-    let mut todos_lock = todos.lock().unwrap_or_else(|e| e.into_inner());
-
-    match _req.uri().path() {
-        "/" => {
-            let cookies = _req.headers().get("Cookie");
-            let mut is_reset = false;
-            for cookie in cookies.iter() {
-                let cookie_result = cookie.to_str();
-                if let Ok(cookie_value) = cookie_result {
-                    let expires_str = cookie_value.split('=').last().unwrap_or_default();
-                    // the cookie string needed to be formatted by removing
-                    // the nano seconds before parsing using crono DateTime
-                    if expires_str.len() >= 19 {
-                        let expiration_time_str = &expires_str[..19];
-                        if let Ok(parsed_datetime) = DateTime::parse_from_str(
-                            &(expiration_time_str.to_owned() + " +00:00"),
-                            "%Y-%m-%d %H:%M:%S %z",
-                        ) {
-                            let system_time_from_datetime = SystemTime::from(parsed_datetime);
-                            if SystemTime::now() > system_time_from_datetime {
-                                is_reset = true;
-                            }
-                        }
-                    }
-                }
-            }
-
-            // let thread_handle;
-            if is_reset || cookies.is_none() {
-                let _ = &todos_lock.clear();
-                let _ = &id_counter.store(0, Ordering::Relaxed);
-            }
-
-            // clone borrow checker on next line
-            let filter_name = selected_filter(filters.clone());
-            // acquire a read to access the filters array
-            let filters_read = filters.read().unwrap();
-            let checked = def_checked(&todos_lock);
-
-            let mk = page(
-                "HTMX • TodoMVC",
-                &filters_read,
-                &todos_lock,
-                checked,
-                has_complete_task(&todos_lock),
-                &filter_name,
-            );
+fn empty_response(status: u16, allow: &str) -> Response {
+    build_response(
+        ResponseBuilder::new().status(status).header("Allow", allow),
+        Body::empty(),
+    )
+}
 
-            let mk_str = mk.into_string();
-
-            if is_reset || cookies.is_none() {
-                // generate randomId string
-                let mut rng = thread_rng();
-                let random_session_id: String = (&mut rng)
-                    .sample_iter(Alphanumeric)
-                    .take(128)
-                    .map(char::from)
-                    .collect();
-
-                let cookie_value =
-                    format!("sessionId={}; Max-Age={}; HttpOnly", random_session_id, 600);
-                return ResponseBuilder::new()
-                    .header("Set-Cookie", cookie_value)
-                    .body(Body::new(mk_str))
-                    .unwrap();
-            }
-            response(200, PreEscaped(mk_str), None)
-        }
-        "/set-hash" => {
-            let filter_name = _req
-                .uri()
-                .query()
-                .and_then(|query| extract_query_param(query, "name"));
-
-            if let Some(name) = filter_name {
-                if !name.is_empty() {
-                    Filter::update_selected_by_property("All".to_string(), &filters, |f| &f.name);
-                } else {
-                    // call to update_selected
-                    Filter::update_selected_by_property(name, &filters, |f| &f.name);
-                }
-            }
-            response(200, PreEscaped(String::new()), None)
-        }
-        "/learn.json" => {
-            let json_str = PreEscaped(serde_json::to_string(&json!({})).unwrap());
-            response(200, json_str, Some("application/json"))
-        }
-        "/update-counts" => {
-            let update_counts_str = update_counts(&todos_lock);
-            let struct_response = PreEscaped(update_counts_str);
-            response(200, struct_response, None)
-        }
-        "/toggle-all" => {
-            let checked = def_checked(&todos_lock);
-            let struct_response = PreEscaped(checked.to_string());
-            response(200, struct_response, None)
-        }
-        "/completed" => {
-            let todo_incomplete = has_complete_task(&todos_lock);
-            if todo_incomplete {
-                let struct_response = clear_completed(todo_incomplete);
-                return response(200, struct_response, None);
-            }
-            response(200, PreEscaped(String::new()), None)
-        }
-        "/footer" => {
-            let filters_read = filters.read().unwrap();
-            let struct_response =
-                footer(&todos_lock, &filters_read, has_complete_task(&todos_lock));
-            response(200, struct_response, None)
-        }
-        "/add-todo" => {
-            let todo_task = _req
-                .uri()
-                .query()
-                .and_then(|query| extract_query_param(query, "task"));
-            let struct_response;
-            if let Some(task) = todo_task {
-                let task_trim = task.trim();
-                if !task_trim.is_empty() {
-                    let todo = Todo::new_id(task_trim.to_string(), false, false, &id_counter);
-                    if todos_lock.len() == 0 {
-                        todos_lock.push(todo);
-                        struct_response = todo_list(&todos_lock, &selected_filter(filters))
-                    } else {
-                        todos_lock.push(todo.clone());
-                        struct_response = build_str_struct(
-                            |todo| todo_item(todo, &selected_filter(filters)),
-                            &todo,
-                        );
-                    }
-                    return response(200, struct_response, None);
-                } else {
-                    return response(200, PreEscaped(String::new()), None);
-                }
-            }
-            response(400, PreEscaped(String::new()), None)
-        }
-        "/toggle-todo" => {
-            let todo_id = _req
-                .uri()
-                .query()
-                .and_then(|query| extract_query_param(query, "id"));
-            if let Some(todo_id_str) = todo_id {
-                if let Ok(todo_id) = todo_id_str.parse::<u32>() {
-                    if let Some(todo) = todos_lock.iter_mut().find(|t| t.id == todo_id) {
-                        todo.done = !todo.done;
-                        let struct_response = build_str_struct(
-                            |todo| todo_item(todo, &selected_filter(filters)),
-                            todo,
-                        );
-                        return response(200, struct_response, None);
-                    }
-                }
-            }
-            response(400, PreEscaped(String::new()), None)
-        }
-        "/edit-todo" => {
-            let todo_id = _req
-                .uri()
-                .query()
-                .and_then(|query| extract_query_param(query, "id"));
-            if let Some(todo_id_str) = todo_id {
-                if let Ok(todo_id) = todo_id_str.parse::<u32>() {
-                    if let Some(todo) = todos_lock.iter_mut().find(|t| t.id == todo_id) {
-                        // clone the todo and update editing to true
-                        let mut clone_todo = todo.clone();
-                        clone_todo.editing = true;
-                        let struct_response =
-                            build_str_struct(|clone_todo| edit_todo(&clone_todo), &clone_todo);
-                        return response(200, struct_response, None);
-                    }
-                }
-            }
-            response(400, PreEscaped(String::new()), None)
+// on the way down, the client should retry later and not reuse this connection
+fn unavailable() -> Response {
+    build_response(
+        ResponseBuilder::new()
+            .status(503)
+            .header("Connection", "close")
+            .header("Retry-After", "5")
+            .header("Content-Type", "text/plain; charset=utf-8"),
+        Body::new("503 Service Unavailable"),
+    )
+}
+
+// endpoints for whoever watch the server rather than use the app, answered before
+// any session is looked up so a scraper polling them never mint one
+type SystemHandler = fn(&App) -> Response;
+
+const SYSTEM_ROUTES: [(&str, SystemHandler); 3] = [
+    ("/metrics", metrics_response),
+    ("/healthz", healthz),
+    ("/readyz", readyz),
+];
+
+fn system_request(req: &Request, app: &App) -> Option<Response> {
+    let (_, render) = SYSTEM_ROUTES
+        .iter()
+        .find(|(path, _)| *path == req.uri().path())?;
+    let res = match req.method().as_str() {
+        "GET" => render(app),
+        "HEAD" => {
+            let mut res = render(app);
+            *res.body_mut() = Body::empty();
+            res
         }
-        "/update-todo" => {
-            let todo_id = _req
-                .uri()
-                .query()
-                .and_then(|query| extract_query_param(query, "id"));
-            let todo_task = _req
-                .uri()
-                .query()
-                .and_then(|query| extract_query_param(query, "task"));
-            let task = todo_task.unwrap_or_else(|| String::new());
-            if let Some(todo_id_str) = todo_id {
-                if let Ok(todo_id) = todo_id_str.parse::<u32>() {
-                    if let Some(todo) = todos_lock.iter_mut().find(|t| t.id == todo_id) {
-                        if !task.trim().is_empty() {
-                            todo.task = task;
-                        } else {
-                            // behave same as remove if user send empty task
-                            todos_lock.retain(|t| t.id != todo_id);
-                            return response(200, PreEscaped(String::new()), None);
-                        }
-                        let struct_response = build_str_struct(
-                            |todo| todo_item(todo, &selected_filter(filters)),
-                            todo,
-                        );
-                        return response(200, struct_response, None);
-                    }
-                }
-            }
-            response(400, PreEscaped(String::new()), None)
+        "OPTIONS" => empty_response(204, "GET, HEAD, OPTIONS"),
+        _ => empty_response(405, "GET, HEAD, OPTIONS"),
+    };
+    Some(res)
+}
+
+// prometheus text format, the todo counts are left out if the storage can't be read
+fn metrics_response(app: &App) -> Response {
+    let (sessions, todos) = match app.sessions.stats() {
+        Ok((sessions, todos)) => (sessions, Some(todos)),
+        Err(e) => {
+            logging::error(
+                "counting todos failed",
+                &[("error", Value::from(e.to_string()))],
+            );
+            (app.sessions.len(), None)
         }
-        "/remove-todo" => {
-            let todo_id = _req
-                .uri()
-                .query()
-                .and_then(|query| extract_query_param(query, "id"));
-            if let Some(todo_id_str) = todo_id {
-                if let Ok(todo_id) = todo_id_str.parse::<u32>() {
-                    todos_lock.retain(|t| t.id != todo_id);
-                    return response(200, PreEscaped(String::new()), None);
-                }
-            }
-            response(400, PreEscaped(String::new()), None)
+    };
+    let body = app.metrics.render(sessions, todos);
+    build_response(
+        ResponseBuilder::new()
+            .status(200)
+            .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+            .header("Content-Length", body.len()),
+        Body::new(body),
+    )
+}
+
+fn json_response(status: u16, body: serde_json::Value) -> Response {
+    let body = body.to_string();
+    build_response(
+        ResponseBuilder::new()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .header("Cache-Control", "no-store")
+            .header("Content-Length", body.len()),
+        Body::new(body),
+    )
+}
+
+// the process is up and answering, nothing else is looked at
+fn healthz(app: &App) -> Response {
+    json_response(
+        200,
+        json!({
+            "status": "ok",
+            "uptime_seconds": app.metrics.uptime().as_secs(),
+        }),
+    )
+}
+
+// ready only when every component is, anything recovered behind the scenes
+// (a poisoned lock) or failing (the storage) turn it into a 503, so does shutting
+// down so the load balancer stop sending traffic while the requests drain
+// sessions quarantined on startup are only reported, they are out of the way
+// already and would otherwise keep the instance out of rotation until someone
+// delete them
+fn readyz(app: &App) -> Response {
+    let quarantined = app.sessions.quarantined();
+    let storage = match app.sessions.check_storage() {
+        Ok(()) => json!({ "status": "ok", "quarantined": quarantined }),
+        Err(e) => {
+            logging::warn(
+                "storage not ready",
+                &[("error", Value::from(e.to_string()))],
+            );
+            json!({ "status": "fail", "error": e.to_string(), "quarantined": quarantined })
         }
-        "/toggle-main" => {
-            let struct_response = toggle_main(&todos_lock, def_checked(&todos_lock));
-            response(200, struct_response, None)
+    };
+    let poisoned = app.sessions.poisoned_locks();
+    let locks = json!({
+        "status": if poisoned == 0 { "ok" } else { "fail" },
+        "poisoned": poisoned,
+    });
+    let assets = json!({
+        "status": if assets::loaded(app.config.assets) { "ok" } else { "fail" },
+        "source": match app.config.assets {
+            AssetSource::Embedded => "embedded",
+            AssetSource::Cdn => "cdn",
+        },
+    });
+    let stopping = app.shutdown.is_stopping();
+    let ready = !stopping
+        && [&storage, &locks, &assets]
+            .iter()
+            .all(|component| component["status"] == "ok");
+    json_response(
+        if ready { 200 } else { 503 },
+        json!({
+            "status": if ready { "ready" } else if stopping { "stopping" } else { "not ready" },
+            "components": {
+                "storage": storage,
+                "locks": locks,
+                "assets": assets,
+            },
+        }),
+    )
+}
+
+// the session behind a response, for the access log
+struct SessionTag(String);
+
+// keep the id a proxy in front already gave the request so it match across services,
+// anything odd looking is replaced rather than echoed back
+fn request_id(req: &Request) -> String {
+    req.headers()
+        .get("X-Request-Id")
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= 64
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        })
+        .map(|id| id.to_string())
+        .unwrap_or_else(|| format!("{:016x}", thread_rng().gen::<u64>()))
+}
+
+// every request go through here, it get an id echoed back in X-Request-Id and
+// tagged on anything logged while it run, then a line in the access log
+fn serve_request(_req: Request, _info: ConnectionInfo, app: Arc<App>) -> Response {
+    let started = Instant::now();
+    let request_id = request_id(&_req);
+    let _scope = logging::request_scope(&request_id);
+    let method = _req.method().to_string();
+    let path = _req.uri().path().to_string();
+
+    let route = SYSTEM_ROUTES
+        .iter()
+        .map(|(system_path, _)| *system_path)
+        .find(|system_path| *system_path == path)
+        .or_else(|| app.router.pattern(&path))
+        .unwrap_or("unmatched");
+
+    let mut res = match system_request(&_req, &app) {
+        Some(res) => res,
+        None => handle_request(_req, _info, Arc::clone(&app)),
+    };
+    if let Ok(header_value) = request_id.parse() {
+        res.headers_mut().insert("X-Request-Id", header_value);
+    }
+
+    let session = res.extensions_mut().remove::<SessionTag>().map(|tag| tag.0);
+    let size = res
+        .headers()
+        .get("Content-Length")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let took = started.elapsed();
+    app.metrics
+        .observe_request(&method, route, res.status().as_u16(), took);
+    let latency_ms = (took.as_secs_f64() * 1_000_000.0).round() / 1000.0;
+    logging::info(
+        "request",
+        &[
+            ("method", Value::from(method)),
+            ("path", Value::from(path)),
+            ("status", Value::from(res.status().as_u16())),
+            ("latency_ms", Value::from(latency_ms)),
+            ("size", Value::from(size)),
+            ("session", Value::from(session)),
+        ],
+    );
+    res
+}
+
+// compress what the handler answered, or answer with the error it failed with,
+// logged and shaped for whoever asked, HEAD get the same headers as GET, just
+// without the body
+fn finish(
+    res: Result<Response, AppError>,
+    encoding: Option<compression::Encoding>,
+    is_head: bool,
+    req: &Request,
+) -> Response {
+    let mut res = res
+        .and_then(|res| compression::compress(encoding, res).map_err(AppError::from))
+        .unwrap_or_else(|e| e.response(req));
+    if is_head {
+        *res.body_mut() = Body::empty();
+    }
+    res
+}
+
+fn handle_request(_req: Request, _info: ConnectionInfo, app: Arc<App>) -> Response {
+    let Some(_in_flight) = app.shutdown.track() else {
+        return unavailable();
+    };
+    let path = _req.uri().path().to_string();
+    let encoding = compression::accepted(&_req);
+    let is_head = _req.method().as_str() == "HEAD";
+    let (handler, params) = match app.router.find(_req.method().as_str(), &path) {
+        Route::Handler(handler, params) => (handler, params),
+        Route::Options(allow) => return empty_response(204, &allow),
+        Route::MethodNotAllowed(allow) => return empty_response(405, &allow),
+        Route::NotFound => {
+            return AppError::NotFound(format!("nothing here at {}", path)).response(&_req);
         }
-        "/toggle-footer" => {
-            let filters_read = filters.read().unwrap();
-            let struct_response =
-                footer(&todos_lock, &filters_read, has_complete_task(&todos_lock));
-            response(200, struct_response, None)
+    };
+    let handler = match handler {
+        Handler::Session(handler) => handler,
+        // nothing in it belong to the visitor, no session is looked up or created
+        Handler::Public(handler) => {
+            return finish(handler(&_req, &app.config), encoding, is_head, &_req);
         }
-        "/todo-list" => {
-            println!("called me!");
-            let struct_response = todo_list(&todos_lock, &selected_filter(filters));
-            response(200, struct_response, None)
+    };
+
+    let cookie_id = app.session_cookie.session_id(&_req);
+
+    // look up the visitor's own session, a fresh visitor or an unknown/expired
+    // cookie get a brand new session instead of clearing everyone's todos
+    let existing = cookie_id.as_deref().and_then(|id| app.sessions.get(id));
+    let (session_id, session, is_new) = match (cookie_id, existing) {
+        (Some(session_id), Some(session)) => (session_id, session, false),
+        _ => match app.sessions.create() {
+            Ok((session_id, session)) => (session_id, session, true),
+            Err(e) => return AppError::from(e).response(&_req),
+        },
+    };
+
+    let mut ctx = Ctx {
+        req: _req,
+        params,
+        session: &session,
+        config: &app.config,
+        metrics: &app.metrics,
+    };
+    let mut res = finish(handler(&mut ctx), encoding, is_head, &ctx.req);
+
+    res.extensions_mut()
+        .insert(SessionTag(session_hash(&session_id)));
+
+    // refresh the cookie on full page load so an active user is not logged out
+    // by the browser while the server still consider the session alive
+    if is_new || TodoFilter::from_path(&path).is_some() {
+        let cookie_value = app
+            .session_cookie
+            .header(&session_id, app.sessions.cookie_max_age());
+        if let Ok(header_value) = cookie_value.parse() {
+            res.headers_mut().insert("Set-Cookie", header_value);
         }
-        "/todo-json" => response(
-            200,
-            PreEscaped(serde_json::to_string(&*todos_lock).unwrap()),
-            Some("application/json"),
-        ),
-        "/todo-item" => {
-            let todo_id = _req
-                .uri()
-                .query()
-                .and_then(|query| extract_query_param(query, "id"));
-            if let Some(todo_id_str) = todo_id {
-                if let Ok(todo_id) = todo_id_str.parse::<u32>() {
-                    if let Some(todo) = todos_lock.iter_mut().find(|t| t.id == todo_id) {
-                        let struct_response = build_str_struct(
-                            |todo| todo_item(todo, &selected_filter(filters)),
-                            todo,
-                        );
-                        return response(200, struct_response, None);
-                    }
-                }
-            }
-            response(400, PreEscaped(String::new()), None)
+    }
+    res
+}
+
+// where the todos are kept:
+// - memory, the default, nothing survive a restart
+// - json, one file per session inside the data dir
+// - eventlog, an append only log of every change per session inside the data dir,
+//   compacted into a snapshot from time to time
+// - sqlite, a single database file, need the sqlite feature
+fn open_backend(storage: &Storage) -> Result<Arc<dyn Backend>, String> {
+    match storage {
+        Storage::Memory => Ok(Arc::new(MemoryBackend)),
+        Storage::Json(dir) => {
+            let backend = JsonBackend::open(dir)
+                .map_err(|e| format!("failed to open data dir {}: {}", dir.display(), e))?;
+            Ok(Arc::new(backend))
         }
-        // serve axe-core for cypress testing
-        "/node_modules/axe-core/axe.min.js" => {
-            if let Ok(js_content) =
-                read_to_string("cypress-example-todomvc/node_modules/axe-core/axe.min.js")
-            {
-                let struct_response = PreEscaped(js_content);
-                response(200, struct_response, Some("application/javascript"))
-            } else {
-                let struct_response = PreEscaped("500 Internal Server Error".to_string());
-                response(500, struct_response, None)
-            }
+        Storage::EventLog(dir) => {
+            let backend = EventLogBackend::open(dir)
+                .map_err(|e| format!("failed to open data dir {}: {}", dir.display(), e))?;
+            Ok(Arc::new(backend))
         }
-        // capture path pattern using str.start_with
-        // path if path.starts_with("/assets/js/") => {
-        //     let path = _req.uri().path();
-        //     if let Ok(js_content) = read_to_string(&path[1..]) {
-        //         let struct_response = PreEscaped(js_content);
-        //         response(200, struct_response, Some("application/javascript"))
-        //     } else {
-        //         let struct_response = PreEscaped("500 Internal Server Error".to_string());
-        //         response(500, struct_response, None)
-        //     }
-        // }
-        _ => {
-            let struct_response = PreEscaped("404 Not Found".to_string());
-            response(404, struct_response, None)
+        #[cfg(feature = "sqlite")]
+        Storage::Sqlite(path) => {
+            let backend = SqliteBackend::open(path)
+                .map_err(|e| format!("failed to open database {}: {}", path.display(), e))?;
+            Ok(Arc::new(backend))
         }
     }
 }
+
+// stop taking requests, let the running ones finish within the timeout
+// and have the stores save what they hold, the result is the exit status
+fn shutdown(app: &App) -> i32 {
+    // the timeout cover the drain and the flush together
+    let deadline = Instant::now() + app.config.shutdown_timeout;
+    app.shutdown.begin();
+    logging::info(
+        "shutting down",
+        &[("in_flight", Value::from(app.shutdown.in_flight()))],
+    );
+    let drained = app.shutdown.drain(app.config.shutdown_timeout);
+    if !drained {
+        logging::warn(
+            "gave up waiting for requests",
+            &[("in_flight", Value::from(app.shutdown.in_flight()))],
+        );
+    }
+    if !app.sessions.flush(deadline) {
+        return EXIT_FLUSH_FAILED;
+    }
+    if !drained {
+        return EXIT_DRAIN_TIMEOUT;
+    }
+    logging::info("stopped", &[]);
+    EXIT_OK
+}
+
+// log why and leave with its own status, the same way a failed shutdown does
+// instead of a panic message and 101
+fn startup_failed(message: &str, e: impl fmt::Display) -> ! {
+    logging::error(message, &[("error", Value::from(e.to_string()))]);
+    process::exit(EXIT_STARTUP_FAILED);
+}
+
 fn main() {
-    // choose 3 different ownership concepts with Atomic, Mutex and RwLock
-    // wrap all in Arc
-    // use Atomic for the id_counter
-    let id_counter = Arc::new(AtomicU32::new(0));
-    // initialize the todos vector, use Mutex, lock for any operations ensure
-    // the atomic counter always sync when the length of the vector goes up
-    let todos = Arc::new(Mutex::new(Vec::new()));
-    // initialize the filters vector, use RwLock
-    // the filters will never change in length with the only changes is for updating
-    // the select parameters, so we do not need to lock with Mutex
-    let filters = Arc::new(RwLock::new(vec![
-        Filter {
-            url: "#/",
-            name: "All",
-            selected: true,
-        },
-        Filter {
-            url: "#/active",
-            name: "Active",
-            selected: false,
-        },
-        Filter {
-            url: "#/completed",
-            name: "Completed",
-            selected: false,
-        },
-    ]));
-
-    Server::bind("localhost:8888")
-        .serve(move |_req, _info| {
-            handle_request(
-                _req,
-                _info,
-                Arc::clone(&id_counter),
-                Arc::clone(&todos),
-                Arc::clone(&filters),
-            )
+    // flags, environment variables and the optional --config file, see --help
+    let config = Config::load();
+    logging::init(config.log_level, config.log_format);
+
+    let backend = open_backend(&config.storage)
+        .unwrap_or_else(|e| startup_failed("failed to open the storage", e));
+
+    // every visitor get their own session holding the todo store (Mutex),
+    // the registry is shared across the server worker threads so wrap it in Arc
+    let sessions = Arc::new(Sessions::new(config.session_lifetime, backend));
+    if let Err(e) = sessions.restore() {
+        startup_failed("failed to restore sessions", e);
+    }
+    spawn_sweeper(Arc::clone(&sessions));
+
+    // without a configured key a random one is generated, with saved todos that
+    // mean every cookie from before the restart is rejected, the restored sessions
+    // can't be reached anymore and the sweeper delete them once they expire
+    if config.session_key.is_none() && !matches!(config.storage, Storage::Memory) {
+        logging::warn(
+            "no SESSION_KEY set, saved todos will be unreachable after a restart and deleted when their session expire",
+            &[],
+        );
+    }
+    let key = config.session_key.clone().unwrap_or_else(Key::generate);
+    let mut server = Server::bind(config.bind_address());
+    if let Some(workers) = config.workers {
+        server = server.max_workers(workers);
+    }
+    logging::info(
+        "listening",
+        &[(
+            "address",
+            Value::from(format!("http://{}", config.bind_address())),
+        )],
+    );
+
+    let app = Arc::new(App {
+        sessions,
+        session_cookie: SessionCookie::new(key, config.secure_cookie),
+        router: routes(),
+        config,
+        shutdown: Shutdown::default(),
+        metrics: Metrics::default(),
+    });
+
+    // SIGINT and SIGTERM wake main up, so does the server stopping on its own
+    let (stop_tx, stop_rx) = mpsc::channel();
+    let signal_tx = stop_tx.clone();
+    ctrlc::set_handler(move || {
+        let _ = signal_tx.send(Stop::Signal);
+    })
+    .unwrap_or_else(|e| startup_failed("failed to install the signal handler", e));
+
+    // serve block its thread for good, run it aside so main is free to wait for a signal
+    let server_app = Arc::clone(&app);
+    thread::Builder::new()
+        .name("server".to_string())
+        .spawn(move || {
+            let served = server
+                .serve(move |_req, _info| serve_request(_req, _info, Arc::clone(&server_app)));
+            let _ = stop_tx.send(Stop::ServerExited(served));
         })
-        .expect("serve failed");
+        .unwrap_or_else(|e| startup_failed("failed to spawn the server", e));
+
+    let code = match stop_rx.recv() {
+        Ok(Stop::Signal) => shutdown(&app),
+        Ok(Stop::ServerExited(Ok(()))) => EXIT_OK,
+        Ok(Stop::ServerExited(Err(e))) => {
+            logging::error("serve failed", &[("error", Value::from(e.to_string()))]);
+            EXIT_SERVE_FAILED
+        }
+        Err(_) => EXIT_SERVE_FAILED,
+    };
+    process::exit(code);
 }
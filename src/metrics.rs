@@ -0,0 +1,220 @@
+use crate::store::Counts;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+// upper bounds in seconds, a request is mostly a lock and a bit of html
+const REQUEST_BUCKETS: [f64; 11] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+];
+// waiting on the todos lock should be next to nothing unless a session is hammered
+const LOCK_BUCKETS: [f64; 9] = [
+    0.000001, 0.00001, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1,
+];
+
+struct Histogram {
+    bounds: &'static [f64],
+    // one per bound, not cumulative, the +Inf bucket is the count
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Histogram {
+        Histogram {
+            bounds,
+            buckets: vec![0; bounds.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, seconds: f64) {
+        if let Some(index) = self.bounds.iter().position(|bound| seconds <= *bound) {
+            self.buckets[index] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, self.count
+        );
+        let braces = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, braces, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braces, self.count);
+    }
+}
+
+// what the app measure about itself, rendered in the prometheus text format,
+// routes are labelled by their pattern (/todos/{id}) so the series stay few
+pub struct Metrics {
+    started: Instant,
+    started_at: SystemTime,
+    requests: Mutex<BTreeMap<(&'static str, &'static str, u16), u64>>,
+    latency: Mutex<BTreeMap<&'static str, Histogram>>,
+    lock_wait: Mutex<Histogram>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+// anything unusual is counted together so a client can't grow the label set
+fn method_label(method: &str) -> &'static str {
+    match method {
+        "GET" => "GET",
+        "HEAD" => "HEAD",
+        "POST" => "POST",
+        "PUT" => "PUT",
+        "PATCH" => "PATCH",
+        "DELETE" => "DELETE",
+        "OPTIONS" => "OPTIONS",
+        _ => "other",
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics {
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            requests: Mutex::new(BTreeMap::new()),
+            latency: Mutex::new(BTreeMap::new()),
+            lock_wait: Mutex::new(Histogram::new(&LOCK_BUCKETS)),
+        }
+    }
+
+    pub fn observe_request(&self, method: &str, route: &'static str, status: u16, took: Duration) {
+        let key = (method_label(method), route, status);
+        *self
+            .requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key)
+            .or_default() += 1;
+        self.latency
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(route)
+            .or_insert_with(|| Histogram::new(&REQUEST_BUCKETS))
+            .observe(took.as_secs_f64());
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn observe_lock_wait(&self, waited: Duration) {
+        self.lock_wait
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .observe(waited.as_secs_f64());
+    }
+
+    // sessions and todos are counted by the caller at scrape time, todos is None
+    // when the storage could not be read
+    pub fn render(&self, sessions: usize, todos: Option<Counts>) -> String {
+        let mut out = String::new();
+
+        out.push_str(
+            "# HELP todomvc_http_requests_total Requests handled, by method, route and status.\n",
+        );
+        out.push_str("# TYPE todomvc_http_requests_total counter\n");
+        for ((method, route, status), count) in self
+            .requests
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "todomvc_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method, route, status, count
+            );
+        }
+
+        out.push_str("# HELP todomvc_http_request_duration_seconds Time spent handling a request, by route.\n");
+        out.push_str("# TYPE todomvc_http_request_duration_seconds histogram\n");
+        for (route, histogram) in self
+            .latency
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+        {
+            histogram.render(
+                &mut out,
+                "todomvc_http_request_duration_seconds",
+                &format!("route=\"{}\"", route),
+            );
+        }
+
+        out.push_str("# HELP todomvc_todos_lock_wait_seconds Time spent waiting for a session's todos lock.\n");
+        out.push_str("# TYPE todomvc_todos_lock_wait_seconds histogram\n");
+        self.lock_wait
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .render(&mut out, "todomvc_todos_lock_wait_seconds", "");
+
+        out.push_str("# HELP todomvc_sessions_active Sessions not yet expired.\n");
+        out.push_str("# TYPE todomvc_sessions_active gauge\n");
+        let _ = writeln!(out, "todomvc_sessions_active {}", sessions);
+
+        if let Some(counts) = todos {
+            out.push_str("# HELP todomvc_todos Todos across every active session.\n");
+            out.push_str("# TYPE todomvc_todos gauge\n");
+            let _ = writeln!(out, "todomvc_todos {}", counts.total);
+            out.push_str(
+                "# HELP todomvc_todos_completed Completed todos across every active session.\n",
+            );
+            out.push_str("# TYPE todomvc_todos_completed gauge\n");
+            let _ = writeln!(out, "todomvc_todos_completed {}", counts.completed);
+            out.push_str(
+                "# HELP todomvc_todos_active Todos left to do across every active session.\n",
+            );
+            out.push_str("# TYPE todomvc_todos_active gauge\n");
+            let _ = writeln!(out, "todomvc_todos_active {}", counts.active());
+        }
+
+        out.push_str("# HELP process_uptime_seconds Seconds since the server started.\n");
+        out.push_str("# TYPE process_uptime_seconds gauge\n");
+        let _ = writeln!(
+            out,
+            "process_uptime_seconds {}",
+            self.started.elapsed().as_secs_f64()
+        );
+        out.push_str("# HELP process_start_time_seconds Start time of the process since unix epoch in seconds.\n");
+        out.push_str("# TYPE process_start_time_seconds gauge\n");
+        let started_at = self
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs_f64())
+            .unwrap_or_default();
+        let _ = writeln!(out, "process_start_time_seconds {}", started_at);
+
+        out
+    }
+}
@@ -0,0 +1,217 @@
+// bind method + path to a handler, the caller only has to deal with the handler
+// while HEAD, OPTIONS and wrong methods are answered from the table itself,
+// a path segment written as {name} capture whatever is there, e.g. /todos/{id},
+// a last segment written as {*name} capture the rest of the path, slashes included
+pub struct Router<H> {
+    routes: Vec<(&'static str, &'static str, H)>,
+}
+
+// the segments captured by {name} in the matched path
+#[derive(Debug, Default)]
+pub struct Params(Vec<(&'static str, String)>);
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(param_name, _)| *param_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub enum Route<H> {
+    Handler(H, Params),
+    // OPTIONS on a known path, carry the Allow header value
+    Options(String),
+    // the path exist but not for this method, carry the Allow header value
+    MethodNotAllowed(String),
+    NotFound,
+}
+
+impl<H: Copy> Default for Router<H> {
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+impl<H: Copy> Router<H> {
+    pub fn new() -> Router<H> {
+        Router { routes: Vec::new() }
+    }
+
+    pub fn route(mut self, method: &'static str, path: &'static str, handler: H) -> Router<H> {
+        self.routes.push((method, path, handler));
+        self
+    }
+
+    pub fn get(self, path: &'static str, handler: H) -> Router<H> {
+        self.route("GET", path, handler)
+    }
+
+    pub fn post(self, path: &'static str, handler: H) -> Router<H> {
+        self.route("POST", path, handler)
+    }
+
+    pub fn patch(self, path: &'static str, handler: H) -> Router<H> {
+        self.route("PATCH", path, handler)
+    }
+
+    pub fn delete(self, path: &'static str, handler: H) -> Router<H> {
+        self.route("DELETE", path, handler)
+    }
+
+    // the pattern the path fall under, a pattern with more static segments win,
+    // so /todos/completed is never taken for /todos/{id}
+    pub fn pattern(&self, path: &str) -> Option<&'static str> {
+        self.routes
+            .iter()
+            .map(|(_, pattern, _)| *pattern)
+            .filter(|pattern| capture(pattern, path).is_some())
+            .max_by_key(|pattern| static_segments(pattern))
+    }
+
+    // HEAD fall back to the GET handler, the caller drop the body
+    pub fn find(&self, method: &str, path: &str) -> Route<H> {
+        let Some(pattern) = self.pattern(path) else {
+            return Route::NotFound;
+        };
+        let methods: Vec<(&str, H)> = self
+            .routes
+            .iter()
+            .filter(|(_, route_path, _)| *route_path == pattern)
+            .map(|(route_method, _, handler)| (*route_method, *handler))
+            .collect();
+
+        let wanted = if method == "HEAD" { "GET" } else { method };
+        if let Some((_, handler)) = methods.iter().find(|(m, _)| *m == wanted) {
+            let params = capture(pattern, path).unwrap_or_default();
+            return Route::Handler(*handler, params);
+        }
+
+        let mut allow: Vec<&str> = methods.iter().map(|(m, _)| *m).collect();
+        if allow.contains(&"GET") {
+            allow.push("HEAD");
+        }
+        allow.push("OPTIONS");
+        let allow = allow.join(", ");
+
+        if method == "OPTIONS" {
+            Route::Options(allow)
+        } else {
+            Route::MethodNotAllowed(allow)
+        }
+    }
+}
+
+fn static_segments(pattern: &str) -> usize {
+    pattern
+        .split('/')
+        .filter(|segment| !segment.starts_with('{'))
+        .count()
+}
+
+// match the path against the pattern segment by segment, None if it doesn't fit
+fn capture(pattern: &'static str, path: &str) -> Option<Params> {
+    let mut pattern_segments = pattern.split('/');
+    let mut path_segments = path.split('/');
+    let mut params = Vec::new();
+    loop {
+        match (pattern_segments.next(), path_segments.next()) {
+            (None, None) => return Some(Params(params)),
+            (Some(expected), Some(segment)) => {
+                if let Some(name) = expected
+                    .strip_prefix("{*")
+                    .and_then(|s| s.strip_suffix('}'))
+                {
+                    let rest: Vec<&str> = std::iter::once(segment).chain(path_segments).collect();
+                    let rest = rest.join("/");
+                    if rest.is_empty() {
+                        return None;
+                    }
+                    params.push((name, rest));
+                    return Some(Params(params));
+                } else if let Some(name) =
+                    expected.strip_prefix('{').and_then(|s| s.strip_suffix('}'))
+                {
+                    if segment.is_empty() {
+                        return None;
+                    }
+                    params.push((name, segment.to_string()));
+                } else if expected != segment {
+                    return None;
+                }
+            }
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router() -> Router<u8> {
+        Router::new()
+            .get("/", 1)
+            .get("/todos/{id}", 2)
+            .patch("/todos/{id}", 3)
+            .get("/todos/completed", 4)
+            .get("/assets/{*path}", 5)
+    }
+
+    fn matched(route: Route<u8>) -> Option<(u8, Params)> {
+        match route {
+            Route::Handler(handler, params) => Some((handler, params)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn capture_named_segments() {
+        let params = capture("/todos/{id}/edit", "/todos/7/edit").unwrap();
+        assert_eq!(params.get("id"), Some("7"));
+        assert!(capture("/todos/{id}/edit", "/todos//edit").is_none());
+        assert!(capture("/todos/{id}/edit", "/todos/7").is_none());
+        assert!(capture("/todos/{id}", "/todos/7/edit").is_none());
+    }
+
+    #[test]
+    fn capture_the_rest_of_the_path() {
+        let params = capture("/assets/{*path}", "/assets/htmx.org@1.9.10/htmx.min.js").unwrap();
+        assert_eq!(params.get("path"), Some("htmx.org@1.9.10/htmx.min.js"));
+        assert!(capture("/assets/{*path}", "/assets/").is_none());
+        assert!(capture("/assets/{*path}", "/assets").is_none());
+    }
+
+    #[test]
+    fn static_segments_win_over_captures() {
+        let (handler, _) = matched(router().find("GET", "/todos/completed")).unwrap();
+        assert_eq!(handler, 4);
+        let (handler, params) = matched(router().find("GET", "/todos/3")).unwrap();
+        assert_eq!(handler, 2);
+        assert_eq!(params.get("id"), Some("3"));
+    }
+
+    #[test]
+    fn head_fall_back_to_get() {
+        let (handler, _) = matched(router().find("HEAD", "/")).unwrap();
+        assert_eq!(handler, 1);
+    }
+
+    #[test]
+    fn wrong_method_carry_the_allowed_ones() {
+        match router().find("DELETE", "/todos/3") {
+            Route::MethodNotAllowed(allow) => assert_eq!(allow, "GET, PATCH, HEAD, OPTIONS"),
+            _ => panic!("expected a 405"),
+        }
+        match router().find("OPTIONS", "/todos/3") {
+            Route::Options(allow) => assert_eq!(allow, "GET, PATCH, HEAD, OPTIONS"),
+            _ => panic!("expected the allowed methods"),
+        }
+    }
+
+    #[test]
+    fn unknown_path_is_not_found() {
+        assert!(matches!(router().find("GET", "/nope"), Route::NotFound));
+    }
+}
@@ -0,0 +1,417 @@
+use crate::logging;
+use crate::store::{Backend, Counts, StoreResult, TodoStore};
+use astra::Request;
+use cookie::{time, Cookie, CookieJar, Key, SameSite};
+use rand::distributions::Alphanumeric;
+use rand::{thread_rng, Rng};
+use serde_json::Value;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, RwLock, TryLockError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+pub const SESSION_COOKIE: &str = "sessionId";
+
+// default idle time before a session and its todos are evicted
+pub const DEFAULT_LIFETIME: Duration = Duration::from_secs(600);
+
+// browsers cap cookie lifetime to 400 days, use it when sessions never expire
+const NEVER_EXPIRE_MAX_AGE: Duration = Duration::from_secs(400 * 24 * 60 * 60);
+
+// how often the sweeper wakes up to look for idle sessions
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+// how often flush retry a store still locked by a request
+const FLUSH_POLL: Duration = Duration::from_millis(10);
+
+// each visitor get their own todos so one user's changes never leak
+// into another user's list
+pub struct Session {
+    // use Mutex, lock for any operations on the store
+    pub todos: Mutex<Box<dyn TodoStore>>,
+    last_seen: Mutex<Instant>,
+}
+
+impl Session {
+    fn new(store: Box<dyn TodoStore>) -> Session {
+        Session {
+            todos: Mutex::new(store),
+            last_seen: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_seen
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .elapsed()
+    }
+}
+
+// registry of all live sessions keyed by the sessionId cookie value,
+// lookups are far more common than inserts so use RwLock
+pub struct Sessions {
+    sessions: RwLock<HashMap<String, Arc<Session>>>,
+    // None means sessions never expire
+    lifetime: Option<Duration>,
+    backend: Arc<dyn Backend>,
+    // sessions moved aside by restore because they failed to load
+    quarantined: AtomicUsize,
+}
+
+impl Sessions {
+    pub fn new(lifetime: Option<Duration>, backend: Arc<dyn Backend>) -> Sessions {
+        Sessions {
+            sessions: RwLock::new(HashMap::new()),
+            lifetime,
+            backend,
+            quarantined: AtomicUsize::new(0),
+        }
+    }
+
+    // Max-Age to hand out on the session cookie
+    pub fn cookie_max_age(&self) -> Duration {
+        self.lifetime.unwrap_or(NEVER_EXPIRE_MAX_AGE)
+    }
+
+    fn is_expired(&self, session: &Session) -> bool {
+        match self.lifetime {
+            Some(lifetime) => session.idle_for() > lifetime,
+            None => false,
+        }
+    }
+
+    // an idle session is treated as gone even if the sweeper has not evicted it yet
+    pub fn get(&self, session_id: &str) -> Option<Arc<Session>> {
+        let sessions_read = self.sessions.read().unwrap_or_else(|e| e.into_inner());
+        let session = sessions_read.get(session_id)?;
+        if self.is_expired(session) {
+            return None;
+        }
+        session.touch();
+        Some(Arc::clone(session))
+    }
+
+    // mint a new random 128 chars session id and register an empty session for it
+    pub fn create(&self) -> StoreResult<(String, Arc<Session>)> {
+        let session_id: String = thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(128)
+            .map(char::from)
+            .collect();
+        let session = Arc::new(Session::new(self.backend.open(&session_id)?));
+
+        let mut sessions_write = self.sessions.write().unwrap_or_else(|e| e.into_inner());
+        sessions_write.insert(session_id.clone(), Arc::clone(&session));
+        Ok((session_id, session))
+    }
+
+    // register every session the backend still hold, they start with a fresh last seen,
+    // a session that fail to load is quarantined so one bad record does not block
+    // startup, nor come back on every restart
+    pub fn restore(&self) -> StoreResult<usize> {
+        let mut restored = 0;
+        for session_id in self.backend.sessions()? {
+            match self.backend.open(&session_id) {
+                Ok(store) => {
+                    let session = Session::new(store);
+                    let mut sessions_write =
+                        self.sessions.write().unwrap_or_else(|e| e.into_inner());
+                    sessions_write.insert(session_id, Arc::new(session));
+                    restored += 1;
+                }
+                Err(e) => {
+                    let hash = session_hash(&session_id);
+                    logging::warn(
+                        "quarantining session",
+                        &[
+                            ("session", Value::from(hash.clone())),
+                            ("error", Value::from(e.to_string())),
+                        ],
+                    );
+                    if let Err(e) = self.backend.quarantine(&session_id) {
+                        logging::error(
+                            "failed to quarantine session",
+                            &[
+                                ("session", Value::from(hash)),
+                                ("error", Value::from(e.to_string())),
+                            ],
+                        );
+                    }
+                    self.quarantined.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        Ok(restored)
+    }
+
+    // the handlers carry on with a poisoned lock, count them here so the
+    // readiness probe can report what was silently recovered, the registry count as one
+    pub fn poisoned_locks(&self) -> usize {
+        let registry = usize::from(self.sessions.is_poisoned());
+        let sessions_read = self.sessions.read().unwrap_or_else(|e| e.into_inner());
+        registry
+            + sessions_read
+                .values()
+                .filter(|session| session.todos.is_poisoned())
+                .count()
+    }
+
+    pub fn check_storage(&self) -> StoreResult<()> {
+        self.backend.check()
+    }
+
+    pub fn quarantined(&self) -> usize {
+        self.quarantined.load(Ordering::Relaxed)
+    }
+
+    // sessions not expired yet
+    pub fn len(&self) -> usize {
+        let sessions_read = self.sessions.read().unwrap_or_else(|e| e.into_inner());
+        sessions_read
+            .values()
+            .filter(|session| !self.is_expired(session))
+            .count()
+    }
+
+    // how many sessions are alive and the todos they hold between them
+    pub fn stats(&self) -> StoreResult<(usize, Counts)> {
+        let sessions_read = self.sessions.read().unwrap_or_else(|e| e.into_inner());
+        let mut active = 0;
+        let mut totals = Counts::default();
+        for session in sessions_read.values() {
+            if self.is_expired(session) {
+                continue;
+            }
+            let counts = session
+                .todos
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .counts()?;
+            active += 1;
+            totals.total += counts.total;
+            totals.completed += counts.completed;
+        }
+        Ok((active, totals))
+    }
+
+    // flush every session's store on shutdown, wait on the todos lock so a request
+    // still writing finish first but never past the deadline, a request stuck
+    // holding the lock must not keep the process from exiting, the session is
+    // skipped and counted as failed, false if any of them failed
+    pub fn flush(&self, deadline: Instant) -> bool {
+        let sessions_read = self.sessions.read().unwrap_or_else(|e| e.into_inner());
+        let mut flushed = true;
+        for (session_id, session) in sessions_read.iter() {
+            let Some(mut todos) = lock_until(&session.todos, deadline) else {
+                logging::error(
+                    "skipped flushing session, its store is still locked",
+                    &[("session", Value::from(session_hash(session_id)))],
+                );
+                flushed = false;
+                continue;
+            };
+            if let Err(e) = todos.flush() {
+                logging::error(
+                    "failed to flush session",
+                    &[
+                        ("session", Value::from(session_hash(session_id))),
+                        ("error", Value::from(e.to_string())),
+                    ],
+                );
+                flushed = false;
+            }
+        }
+        flushed
+    }
+
+    // evict every idle session along with its todos, return how many were dropped
+    pub fn sweep(&self) -> usize {
+        let mut sessions_write = self.sessions.write().unwrap_or_else(|e| e.into_inner());
+        let mut evicted = Vec::new();
+        sessions_write.retain(|session_id, session| {
+            let expired = self.is_expired(session);
+            if expired {
+                evicted.push(session_id.clone());
+            }
+            !expired
+        });
+        drop(sessions_write);
+
+        for session_id in &evicted {
+            if let Err(e) = self.backend.remove(session_id) {
+                logging::error(
+                    "failed to remove session",
+                    &[
+                        ("session", Value::from(session_hash(session_id))),
+                        ("error", Value::from(e.to_string())),
+                    ],
+                );
+            }
+        }
+        evicted.len()
+    }
+}
+
+// try the lock until it is free or the deadline pass, at least once even when
+// the deadline is already gone
+fn lock_until<T: ?Sized>(mutex: &Mutex<T>, deadline: Instant) -> Option<MutexGuard<'_, T>> {
+    loop {
+        match mutex.try_lock() {
+            Ok(guard) => return Some(guard),
+            Err(TryLockError::Poisoned(e)) => return Some(e.into_inner()),
+            Err(TryLockError::WouldBlock) if Instant::now() >= deadline => return None,
+            Err(TryLockError::WouldBlock) => thread::sleep(FLUSH_POLL),
+        }
+    }
+}
+
+// background thread that periodically evicts idle sessions,
+// nothing to do when sessions never expire
+pub fn spawn_sweeper(sessions: Arc<Sessions>) -> Option<thread::JoinHandle<()>> {
+    let lifetime = sessions.lifetime?;
+    let interval = SWEEP_INTERVAL.min(lifetime);
+    let handle = thread::Builder::new()
+        .name("session-sweeper".to_string())
+        .spawn(move || loop {
+            thread::sleep(interval);
+            sessions.sweep();
+        })
+        .expect("failed to spawn session sweeper");
+    Some(handle)
+}
+
+// parse a session lifetime in seconds, "never" (or 0) disable expiry
+pub fn parse_lifetime(value: &str) -> Result<Option<Duration>, String> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("never") {
+        return Ok(None);
+    }
+    match value.parse::<u64>() {
+        Ok(0) => Ok(None),
+        Ok(secs) => Ok(Some(Duration::from_secs(secs))),
+        Err(_) => Err(format!(
+            "invalid session lifetime {:?}, expected seconds or \"never\"",
+            value
+        )),
+    }
+}
+
+// the session id travel in a signed cookie, a client can not forge or guess
+// another user's id since the value is useless without the server key
+pub struct SessionCookie {
+    key: Key,
+    secure: bool,
+}
+
+impl SessionCookie {
+    pub fn new(key: Key, secure: bool) -> SessionCookie {
+        SessionCookie { key, secure }
+    }
+
+    // collect every cookie the client sent, then only trust the session id
+    // if its signature verify against our key
+    pub fn session_id(&self, req: &Request) -> Option<String> {
+        let mut jar = CookieJar::new();
+        for header in req.headers().get_all("Cookie") {
+            let Ok(header_str) = header.to_str() else {
+                continue;
+            };
+            for cookie in Cookie::split_parse(header_str).flatten() {
+                jar.add_original(cookie.into_owned());
+            }
+        }
+        let cookie = jar.signed(&self.key).get(SESSION_COOKIE)?;
+        Some(cookie.value().to_string())
+    }
+
+    // signed Set-Cookie header value for the given session id
+    pub fn header(&self, session_id: &str, max_age: Duration) -> String {
+        let cookie = Cookie::build((SESSION_COOKIE, session_id.to_string()))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .secure(self.secure)
+            .max_age(time::Duration::seconds(max_age.as_secs() as i64));
+
+        let mut jar = CookieJar::new();
+        jar.signed_mut(&self.key).add(cookie);
+        jar.get(SESSION_COOKIE)
+            .map(|cookie| cookie.to_string())
+            .unwrap_or_default()
+    }
+}
+
+// a fixed secret keep cookies valid across restarts, it need to be at least 64 bytes
+pub fn parse_key(secret: &str) -> Result<Key, String> {
+    Key::try_from(secret.as_bytes()).map_err(|e| format!("invalid session key: {}", e))
+}
+
+// the session id is as good as the cookie, logs only ever get a hash of it
+pub fn session_hash(session_id: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    session_id.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use astra::Body;
+
+    fn request(cookie: &str) -> Request {
+        let mut req = Request::new(Body::empty());
+        req.headers_mut().insert("Cookie", cookie.parse().unwrap());
+        req
+    }
+
+    // the name=value part of a Set-Cookie header, what the browser send back
+    fn sent_back(set_cookie: &str) -> &str {
+        set_cookie.split(';').next().unwrap()
+    }
+
+    #[test]
+    fn signed_cookie_round_trip() {
+        let cookies = SessionCookie::new(Key::generate(), false);
+        let header = cookies.header("abc123", DEFAULT_LIFETIME);
+        assert!(header.contains("HttpOnly"));
+        assert!(header.contains("Max-Age=600"));
+        let req = request(&format!("theme=dark; {}", sent_back(&header)));
+        assert_eq!(cookies.session_id(&req).as_deref(), Some("abc123"));
+    }
+
+    #[test]
+    fn cookie_signed_with_another_key_is_refused() {
+        let ours = SessionCookie::new(Key::generate(), false);
+        let theirs = SessionCookie::new(Key::generate(), false);
+        let header = theirs.header("abc123", DEFAULT_LIFETIME);
+        assert_eq!(ours.session_id(&request(sent_back(&header))), None);
+    }
+
+    #[test]
+    fn tampered_or_unsigned_cookie_is_refused() {
+        let cookies = SessionCookie::new(Key::generate(), false);
+        let header = cookies.header("abc123", DEFAULT_LIFETIME);
+        let tampered = sent_back(&header).replace("abc123", "abc124");
+        assert_eq!(cookies.session_id(&request(&tampered)), None);
+        assert_eq!(cookies.session_id(&request("sessionId=abc123")), None);
+    }
+
+    #[test]
+    fn secure_flag_follow_the_config() {
+        let header = SessionCookie::new(Key::generate(), true).header("abc123", DEFAULT_LIFETIME);
+        assert!(header.contains("Secure"));
+        let header = SessionCookie::new(Key::generate(), false).header("abc123", DEFAULT_LIFETIME);
+        assert!(!header.contains("Secure"));
+    }
+}
@@ -0,0 +1,70 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+// exit status of the process, so a supervisor can tell a clean stop from a bad one
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_SERVE_FAILED: i32 = 1;
+// the storage, the signal handler or the server thread could not be set up
+pub const EXIT_STARTUP_FAILED: i32 = 2;
+pub const EXIT_DRAIN_TIMEOUT: i32 = 3;
+pub const EXIT_FLUSH_FAILED: i32 = 4;
+
+// how often drain look at the in-flight count
+const DRAIN_POLL: Duration = Duration::from_millis(20);
+
+// astra give no way to close the listener, so once stopping every new request is
+// turned away while the ones already running are counted until they are done
+#[derive(Default)]
+pub struct Shutdown {
+    stopping: AtomicBool,
+    in_flight: AtomicUsize,
+}
+
+// held for as long as a request is handled
+pub struct InFlight<'a>(&'a Shutdown);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Shutdown {
+    // None once stopping, count first and check after so drain never miss a
+    // request that slipped in right as the flag was set
+    pub fn track(&self) -> Option<InFlight<'_>> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        let in_flight = InFlight(self);
+        if self.is_stopping() {
+            return None;
+        }
+        Some(in_flight)
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    pub fn begin(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    // wait for the running requests to finish, false if some are still going at the timeout
+    pub fn drain(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.in_flight.load(Ordering::SeqCst) != 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(DRAIN_POLL);
+        }
+        true
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+}
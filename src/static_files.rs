@@ -0,0 +1,175 @@
+use crate::build_response;
+use astra::{Body, Request, Response, ResponseBuilder};
+use percent_encoding::percent_decode_str;
+use std::{
+    fs::{self, File},
+    io,
+    path::{Component, Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use time::OffsetDateTime;
+
+// only plain names below the root, anything that could climb out of it
+// (.., an absolute path, a drive prefix) is refused, the path come percent-encoded
+// from the url and is checked once decoded so %2e%2e is no way around it
+fn resolve(root: &Path, relative: &str) -> Option<PathBuf> {
+    let relative = percent_decode_str(relative).decode_utf8().ok()?;
+    if relative.is_empty() || relative.contains(['\\', '\0']) {
+        return None;
+    }
+    let relative = Path::new(relative.as_ref());
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    Some(root.join(relative))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "application/javascript",
+        "json" | "map" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "ico" => "image/x-icon",
+        "webp" => "image/webp",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+// IMF-fixdate as used by Last-Modified, e.g. Sun, 06 Nov 1994 08:49:37 GMT
+fn http_date(modified: SystemTime) -> String {
+    let date = OffsetDateTime::from(modified);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        &date.weekday().to_string()[..3],
+        date.day(),
+        &date.month().to_string()[..3],
+        date.year(),
+        date.hour(),
+        date.minute(),
+        date.second(),
+    )
+}
+
+// weak since it come from the size and modified time, not the bytes themselves
+fn etag(len: u64, modified: SystemTime) -> String {
+    let secs = modified
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    format!("W/\"{:x}-{:x}\"", len, secs)
+}
+
+fn not_modified(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag.trim_start_matches("W/"))
+}
+
+// serve the file at relative below root, None when there is no such file,
+// the body is streamed from disk so any size and any bytes go through
+pub fn serve(root: &Path, relative: &str, req: &Request) -> io::Result<Option<Response>> {
+    let Some(path) = resolve(root, relative) else {
+        return Ok(None);
+    };
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        Ok(_) => return Ok(None),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let modified = metadata.modified()?;
+    let etag = etag(metadata.len(), modified);
+
+    // the browser already has this version, let it use its copy
+    let if_none_match = req
+        .headers()
+        .get("If-None-Match")
+        .and_then(|value| value.to_str().ok());
+    let response_builder = ResponseBuilder::new()
+        .header("ETag", &etag)
+        .header("Last-Modified", http_date(modified))
+        .header("Cache-Control", "no-cache");
+    if if_none_match.is_some_and(|value| not_modified(value, &etag)) {
+        return Ok(Some(build_response(
+            response_builder.status(304),
+            Body::empty(),
+        )));
+    }
+
+    let file = File::open(&path)?;
+    Ok(Some(build_response(
+        response_builder
+            .header("Content-Type", content_type(&path))
+            .header("Content-Length", metadata.len())
+            .status(200),
+        Body::wrap_reader(file),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_plain_paths_below_the_root() {
+        let root = Path::new("/srv/static");
+        assert_eq!(
+            resolve(root, "css/app.css"),
+            Some(PathBuf::from("/srv/static/css/app.css"))
+        );
+        assert_eq!(
+            resolve(root, "my%20file.txt"),
+            Some(PathBuf::from("/srv/static/my file.txt"))
+        );
+    }
+
+    #[test]
+    fn resolve_refuse_climbing_out() {
+        let root = Path::new("/srv/static");
+        for relative in [
+            "..",
+            "../secret",
+            "css/../../secret",
+            "%2e%2e/secret",
+            "%2E%2E%2Fsecret",
+            "css/%2e%2e/%2e%2e/secret",
+            "./app.css",
+        ] {
+            assert_eq!(resolve(root, relative), None, "{}", relative);
+        }
+    }
+
+    #[test]
+    fn resolve_refuse_absolute_and_odd_paths() {
+        let root = Path::new("/srv/static");
+        for relative in [
+            "/etc/passwd",
+            "%2fetc/passwd",
+            "..\\secret",
+            "%5c..%5csecret",
+            "app.css%00.png",
+            "%ff",
+            "",
+        ] {
+            assert_eq!(resolve(root, relative), None, "{}", relative);
+        }
+    }
+}
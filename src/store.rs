@@ -0,0 +1,160 @@
+use crate::Todo;
+use rand::{thread_rng, Rng};
+use serde::Serialize;
+use std::{
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+mod eventlog;
+mod json;
+mod memory;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use eventlog::EventLogBackend;
+pub use json::JsonBackend;
+pub use memory::MemoryBackend;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteBackend;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io(e) => write!(f, "storage io error: {}", e),
+            StoreError::Json(e) => write!(f, "storage json error: {}", e),
+            #[cfg(feature = "sqlite")]
+            StoreError::Sqlite(e) => write!(f, "storage sqlite error: {}", e),
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+impl From<io::Error> for StoreError {
+    fn from(e: io::Error) -> Self {
+        StoreError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for StoreError {
+    fn from(e: serde_json::Error) -> Self {
+        StoreError::Json(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for StoreError {
+    fn from(e: rusqlite::Error) -> Self {
+        StoreError::Sqlite(e)
+    }
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counts {
+    pub total: usize,
+    pub completed: usize,
+}
+
+impl Counts {
+    pub fn active(&self) -> usize {
+        self.total - self.completed
+    }
+}
+
+// the todos of a single session, every route go through this
+// so the storage can be swapped without touching the handlers
+pub trait TodoStore: Send {
+    fn list(&self) -> StoreResult<Vec<Todo>>;
+    fn get(&self, id: u32) -> StoreResult<Option<Todo>>;
+    // the store decide the id of the new todo
+    fn insert(&mut self, task: String) -> StoreResult<Todo>;
+    // replace the todo with the same id, false if there is no such todo
+    fn update(&mut self, todo: &Todo) -> StoreResult<bool>;
+    fn remove(&mut self, id: u32) -> StoreResult<bool>;
+    fn clear_completed(&mut self) -> StoreResult<usize>;
+    fn toggle_all(&mut self, done: bool) -> StoreResult<()>;
+    fn counts(&self) -> StoreResult<Counts>;
+    // called once on shutdown, every change is already saved by the time a call
+    // return so only a store with something to tidy up need it
+    fn flush(&mut self) -> StoreResult<()> {
+        Ok(())
+    }
+}
+
+// hand out a store per session and keep track of the sessions already saved
+pub trait Backend: Send + Sync {
+    // open the session's store, a session never seen before start empty and
+    // nothing is saved for it until its first change, so a visitor that never
+    // change anything (a bot, curl, a health check) leave nothing behind
+    fn open(&self, session_id: &str) -> StoreResult<Box<dyn TodoStore>>;
+    // every session the backend still hold, used to restore them on startup
+    fn sessions(&self) -> StoreResult<Vec<String>>;
+    fn remove(&self, session_id: &str) -> StoreResult<()>;
+    // make sure the storage can still be read and written, for the readiness probe,
+    // polled every few seconds so it stay cheap, the saved data itself is
+    // checked as it is loaded on startup
+    fn check(&self) -> StoreResult<()>;
+    // move a session that failed to load out of the way so it is neither restored
+    // nor listed again, left on disk for whoever want to look at it, sqlite
+    // check the whole database on open instead so it keep the default
+    fn quarantine(&self, _session_id: &str) -> StoreResult<()> {
+        Ok(())
+    }
+}
+
+// the session ids of the files in dir ending with .extension, session ids are
+// always alphanumeric so anything else sitting there is not ours
+fn list_sessions(dir: &Path, extension: &str) -> StoreResult<Vec<String>> {
+    let mut session_ids = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(extension) {
+            continue;
+        }
+        let Some(session_id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if session_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            session_ids.push(session_id.to_string());
+        }
+    }
+    Ok(session_ids)
+}
+
+// write to a temp file first then rename over the old one, rename is atomic
+// so a crash mid write never leave a half written file behind
+fn write_atomic(path: &Path, value: &impl Serialize) -> StoreResult<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+
+    let mut file = File::create(&tmp_path)?;
+    serde_json::to_writer(&mut file, value)?;
+    file.flush()?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+// write and delete a probe file so a folder gone read-only or missing is noticed
+// before a visitor's change fail on it, named at random since probes from two
+// requests can overlap and one would delete the other's file under it
+fn probe_dir(dir: &Path) -> StoreResult<()> {
+    let probe = dir.join(format!(".probe-{:016x}", thread_rng().gen::<u64>()));
+    fs::write(&probe, b"ok")?;
+    fs::remove_file(&probe)?;
+    Ok(())
+}
@@ -0,0 +1,496 @@
+use super::memory::{MemoryStore, Snapshot};
+use super::{Backend, Counts, StoreResult, TodoStore};
+use crate::logging;
+use crate::Todo;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+// compact the log into a snapshot once this many events piled up
+const COMPACT_EVERY: usize = 100;
+
+// one line of the log, every mutation handled by the server end up as one of these
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    Added { todo: Todo },
+    Toggled { id: u32, done: bool },
+    Renamed { id: u32, task: String },
+    Removed { id: u32 },
+    Cleared,
+    ToggledAll { done: bool },
+}
+
+// seq only ever goes up, a snapshot remember the last seq it include so
+// replaying never apply an event twice even if compaction was cut short
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    seq: u64,
+    // unix seconds, for the audit trail
+    at: u64,
+    #[serde(flatten)]
+    event: Event,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LogSnapshot {
+    seq: u64,
+    #[serde(flatten)]
+    state: Snapshot,
+}
+
+fn apply(memory: &mut MemoryStore, event: Event) -> StoreResult<()> {
+    match event {
+        Event::Added { todo } => memory.push(todo),
+        Event::Toggled { id, done } => {
+            if let Some(mut todo) = memory.get(id)? {
+                todo.done = done;
+                memory.update(&todo)?;
+            }
+        }
+        Event::Renamed { id, task } => {
+            if let Some(mut todo) = memory.get(id)? {
+                todo.task = task;
+                memory.update(&todo)?;
+            }
+        }
+        Event::Removed { id } => {
+            memory.remove(id)?;
+        }
+        Event::Cleared => {
+            memory.clear_completed()?;
+        }
+        Event::ToggledAll { done } => memory.toggle_all(done)?,
+    }
+    Ok(())
+}
+
+// per session a <id>.log of JSON lines plus the <id>.snapshot.json it compact into
+#[derive(Debug)]
+pub struct EventLogBackend {
+    dir: PathBuf,
+}
+
+impl EventLogBackend {
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<EventLogBackend> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(EventLogBackend { dir })
+    }
+
+    fn log_path(&self, session_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.log", session_id))
+    }
+
+    fn snapshot_path(&self, session_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.snapshot.json", session_id))
+    }
+
+    // rebuild the state from the snapshot then replay whatever was logged after it,
+    // the files are only read
+    fn load(&self, session_id: &str) -> StoreResult<(MemoryStore, u64, Replayed)> {
+        let (mut memory, snapshot_seq) = match fs::read(self.snapshot_path(session_id)) {
+            Ok(content) => {
+                let snapshot: LogSnapshot = serde_json::from_slice(&content)?;
+                (MemoryStore::from_snapshot(snapshot.state), snapshot.seq)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (MemoryStore::default(), 0),
+            Err(e) => return Err(e.into()),
+        };
+        let replayed = replay(&mut memory, &self.log_path(session_id), snapshot_seq)?;
+        Ok((memory, snapshot_seq, replayed))
+    }
+}
+
+impl Backend for EventLogBackend {
+    fn open(&self, session_id: &str) -> StoreResult<Box<dyn TodoStore>> {
+        let log_path = self.log_path(session_id);
+        let snapshot_path = self.snapshot_path(session_id);
+        let (memory, snapshot_seq, replayed) = self.load(session_id)?;
+
+        // the log is only created by the first event
+        let log = match OpenOptions::new().append(true).open(&log_path) {
+            Ok(mut log) => {
+                // drop a corrupted tail so the next event start on a clean line
+                log.set_len(replayed.valid_len)?;
+                if replayed.missing_newline {
+                    log.write_all(b"\n")?;
+                }
+                Some(log)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Box::new(EventLogStore {
+            memory,
+            log,
+            log_path,
+            snapshot_path,
+            seq: replayed.last_seq.max(snapshot_seq),
+            since_snapshot: replayed.events,
+        }))
+    }
+
+    fn sessions(&self) -> StoreResult<Vec<String>> {
+        super::list_sessions(&self.dir, "log")
+    }
+
+    fn remove(&self, session_id: &str) -> StoreResult<()> {
+        for path in [self.log_path(session_id), self.snapshot_path(session_id)] {
+            match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn check(&self) -> StoreResult<()> {
+        super::probe_dir(&self.dir)?;
+        self.sessions()?;
+        Ok(())
+    }
+
+    fn quarantine(&self, session_id: &str) -> StoreResult<()> {
+        for path in [self.log_path(session_id), self.snapshot_path(session_id)] {
+            let mut corrupt = path.clone().into_os_string();
+            corrupt.push(".corrupt");
+            match fs::rename(&path, corrupt) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Replayed {
+    last_seq: u64,
+    // events applied on top of the snapshot
+    events: usize,
+    // bytes of the log worth keeping
+    valid_len: u64,
+    missing_newline: bool,
+}
+
+// a line that fail to parse is only forgiven when it is the last one, that is
+// what a crash mid append look like, anything earlier mean the log is damaged
+fn replay(memory: &mut MemoryStore, log_path: &Path, after_seq: u64) -> StoreResult<Replayed> {
+    let content = match fs::read(log_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut replayed = Replayed {
+        last_seq: 0,
+        events: 0,
+        valid_len: 0,
+        missing_newline: false,
+    };
+    let mut offset = 0;
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        offset += line.len();
+        let is_last = offset == content.len();
+        let has_newline = line.ends_with(b"\n");
+        let json = line.strip_suffix(b"\n").unwrap_or(line);
+        if json.iter().all(|b| b.is_ascii_whitespace()) {
+            replayed.valid_len = offset as u64;
+            continue;
+        }
+
+        match serde_json::from_slice::<Entry>(json) {
+            Ok(entry) => {
+                if entry.seq > after_seq {
+                    apply(memory, entry.event)?;
+                    replayed.events += 1;
+                }
+                replayed.last_seq = entry.seq;
+                replayed.valid_len = offset as u64;
+                replayed.missing_newline = !has_newline;
+            }
+            Err(e) if is_last => {
+                logging::warn(
+                    "ignoring corrupted last line",
+                    &[
+                        ("file", Value::from(log_path.display().to_string())),
+                        ("error", Value::from(e.to_string())),
+                    ],
+                );
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(replayed)
+}
+
+// serve reads from memory, every change is appended to the log before returning
+struct EventLogStore {
+    memory: MemoryStore,
+    // None until the session log its first event
+    log: Option<File>,
+    log_path: PathBuf,
+    snapshot_path: PathBuf,
+    seq: u64,
+    since_snapshot: usize,
+}
+
+impl EventLogStore {
+    // the events of one change go out in a single write, a write that fail
+    // halfway is cut back off so the log never keep part of a change
+    fn append(&mut self, events: Vec<Event>) -> StoreResult<()> {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let count = events.len();
+        let mut seq = self.seq;
+        let mut lines = Vec::new();
+        for event in events {
+            seq += 1;
+            serde_json::to_writer(&mut lines, &Entry { seq, at, event })?;
+            lines.push(b'\n');
+        }
+        let log = match &mut self.log {
+            Some(log) => log,
+            None => self.log.insert(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.log_path)?,
+            ),
+        };
+        let len = log.metadata()?.len();
+        if let Err(e) = log.write_all(&lines).and_then(|()| log.sync_data()) {
+            let _ = log.set_len(len);
+            return Err(e.into());
+        }
+        self.since_snapshot += count;
+        self.seq = seq;
+        Ok(())
+    }
+
+    // same as JsonStore, the change is made on a copy that is only kept once
+    // its events are in the log
+    fn commit<T>(
+        &mut self,
+        change: impl FnOnce(&mut MemoryStore) -> StoreResult<(T, Vec<Event>)>,
+    ) -> StoreResult<T> {
+        let mut memory = self.memory.clone();
+        let (result, events) = change(&mut memory)?;
+        if events.is_empty() {
+            return Ok(result);
+        }
+        self.append(events)?;
+        self.memory = memory;
+
+        // the change is already safe in the log, a failed compaction is only
+        // tried again on the next event
+        if self.since_snapshot >= COMPACT_EVERY {
+            if let Err(e) = self.compact() {
+                logging::warn(
+                    "compaction failed",
+                    &[
+                        (
+                            "file",
+                            Value::from(self.snapshot_path.display().to_string()),
+                        ),
+                        ("error", Value::from(e.to_string())),
+                    ],
+                );
+            }
+        }
+        Ok(result)
+    }
+
+    // write the snapshot atomically first, only then empty the log, if we die
+    // in between the seq in the snapshot make the leftover events a no-op
+    fn compact(&mut self) -> StoreResult<()> {
+        let snapshot = LogSnapshot {
+            seq: self.seq,
+            state: self.memory.snapshot(),
+        };
+        super::write_atomic(&self.snapshot_path, &snapshot)?;
+
+        if let Some(log) = &mut self.log {
+            log.set_len(0)?;
+            log.sync_all()?;
+        }
+        self.since_snapshot = 0;
+        Ok(())
+    }
+}
+
+impl TodoStore for EventLogStore {
+    fn list(&self) -> StoreResult<Vec<Todo>> {
+        self.memory.list()
+    }
+
+    fn get(&self, id: u32) -> StoreResult<Option<Todo>> {
+        self.memory.get(id)
+    }
+
+    fn insert(&mut self, task: String) -> StoreResult<Todo> {
+        self.commit(|memory| {
+            let todo = memory.insert(task)?;
+            Ok((todo.clone(), vec![Event::Added { todo }]))
+        })
+    }
+
+    // a single update can both rename and toggle, log each change on its own
+    fn update(&mut self, todo: &Todo) -> StoreResult<bool> {
+        self.commit(|memory| {
+            let Some(stored) = memory.get(todo.id)? else {
+                return Ok((false, Vec::new()));
+            };
+            memory.update(todo)?;
+            let mut events = Vec::new();
+            if stored.task != todo.task {
+                events.push(Event::Renamed {
+                    id: todo.id,
+                    task: todo.task.clone(),
+                });
+            }
+            if stored.done != todo.done {
+                events.push(Event::Toggled {
+                    id: todo.id,
+                    done: todo.done,
+                });
+            }
+            Ok((true, events))
+        })
+    }
+
+    fn remove(&mut self, id: u32) -> StoreResult<bool> {
+        self.commit(|memory| {
+            let found = memory.remove(id)?;
+            let events = if found {
+                vec![Event::Removed { id }]
+            } else {
+                Vec::new()
+            };
+            Ok((found, events))
+        })
+    }
+
+    fn clear_completed(&mut self) -> StoreResult<usize> {
+        self.commit(|memory| {
+            let cleared = memory.clear_completed()?;
+            let events = if cleared > 0 {
+                vec![Event::Cleared]
+            } else {
+                Vec::new()
+            };
+            Ok((cleared, events))
+        })
+    }
+
+    fn toggle_all(&mut self, done: bool) -> StoreResult<()> {
+        self.commit(|memory| {
+            memory.toggle_all(done)?;
+            Ok(((), vec![Event::ToggledAll { done }]))
+        })
+    }
+
+    fn counts(&self) -> StoreResult<Counts> {
+        self.memory.counts()
+    }
+
+    // leave a fresh snapshot behind so the next start has no events to replay
+    fn flush(&mut self) -> StoreResult<()> {
+        if self.since_snapshot > 0 {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::TodoStore;
+
+    fn added(seq: u64, id: u32, task: &str) -> String {
+        format!(
+            r#"{{"seq":{},"at":0,"event":"added","todo":{{"id":{},"task":"{}","done":false,"editing":false}}}}"#,
+            seq, id, task
+        ) + "\n"
+    }
+
+    // each test get its own file, they run in parallel
+    fn write_log(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "todomvc-replay-{}-{}.log",
+            name,
+            std::process::id()
+        ));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn replay_forgive_a_corrupted_last_line() {
+        let valid = added(1, 0, "a") + &added(2, 1, "b");
+        let path = write_log("last-line", &(valid.clone() + r#"{"seq":3,"at":0,"ev"#));
+        let mut memory = MemoryStore::default();
+        let replayed = replay(&mut memory, &path, 0).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(replayed.events, 2);
+        assert_eq!(replayed.last_seq, 2);
+        assert_eq!(replayed.valid_len, valid.len() as u64);
+        assert!(!replayed.missing_newline);
+        assert_eq!(memory.list().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn replay_reject_a_corrupted_line_in_the_middle() {
+        let content = added(1, 0, "a") + "not json\n" + &added(2, 1, "b");
+        let path = write_log("middle-line", &content);
+        let result = replay(&mut MemoryStore::default(), &path, 0);
+        fs::remove_file(&path).unwrap();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn replay_skip_events_already_in_the_snapshot() {
+        let content = added(1, 0, "a") + &added(2, 1, "b") + &added(3, 2, "c");
+        let path = write_log("after-seq", &content);
+        let mut memory = MemoryStore::default();
+        let replayed = replay(&mut memory, &path, 2).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(replayed.events, 1);
+        assert_eq!(replayed.last_seq, 3);
+        let todos = memory.list().unwrap();
+        assert_eq!(todos.len(), 1);
+        assert_eq!(todos[0].task, "c");
+    }
+
+    #[test]
+    fn replay_note_a_last_line_without_newline() {
+        let content = added(1, 0, "a");
+        let path = write_log("no-newline", content.trim_end());
+        let replayed = replay(&mut MemoryStore::default(), &path, 0).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(replayed.events, 1);
+        assert!(replayed.missing_newline);
+    }
+
+    #[test]
+    fn replay_of_a_missing_log_is_empty() {
+        let path = std::env::temp_dir().join("todomvc-replay-missing-never-written.log");
+        let replayed = replay(&mut MemoryStore::default(), &path, 0).unwrap();
+
+        assert_eq!(replayed.events, 0);
+        assert_eq!(replayed.valid_len, 0);
+    }
+}
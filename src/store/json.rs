@@ -0,0 +1,124 @@
+use super::memory::MemoryStore;
+use super::{Backend, Counts, StoreResult, TodoStore};
+use crate::Todo;
+use std::{fs, io, path::PathBuf};
+
+// one JSON file per session inside the data directory, named after the session id
+#[derive(Debug)]
+pub struct JsonBackend {
+    dir: PathBuf,
+}
+
+impl JsonBackend {
+    pub fn open(dir: impl Into<PathBuf>) -> io::Result<JsonBackend> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(JsonBackend { dir })
+    }
+
+    fn path(&self, session_id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", session_id))
+    }
+
+    fn load(&self, session_id: &str) -> StoreResult<MemoryStore> {
+        match fs::read(self.path(session_id)) {
+            Ok(content) => Ok(MemoryStore::from_snapshot(serde_json::from_slice(
+                &content,
+            )?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(MemoryStore::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Backend for JsonBackend {
+    fn open(&self, session_id: &str) -> StoreResult<Box<dyn TodoStore>> {
+        let memory = self.load(session_id)?;
+        Ok(Box::new(JsonStore {
+            memory,
+            path: self.path(session_id),
+        }))
+    }
+
+    fn sessions(&self) -> StoreResult<Vec<String>> {
+        super::list_sessions(&self.dir, "json")
+    }
+
+    fn remove(&self, session_id: &str) -> StoreResult<()> {
+        match fs::remove_file(self.path(session_id)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn check(&self) -> StoreResult<()> {
+        super::probe_dir(&self.dir)?;
+        self.sessions()?;
+        Ok(())
+    }
+
+    fn quarantine(&self, session_id: &str) -> StoreResult<()> {
+        let path = self.path(session_id);
+        fs::rename(&path, path.with_extension("json.corrupt"))?;
+        Ok(())
+    }
+}
+
+// serve reads from memory and write the whole session to disk after every change
+struct JsonStore {
+    memory: MemoryStore,
+    path: PathBuf,
+}
+
+impl JsonStore {
+    // apply the change to a copy and only keep it once it is on disk, a failed
+    // write leave the session as it was instead of showing a change that is lost
+    // on the next restart
+    fn commit<T>(
+        &mut self,
+        change: impl FnOnce(&mut MemoryStore) -> StoreResult<T>,
+        changed: impl FnOnce(&T) -> bool,
+    ) -> StoreResult<T> {
+        let mut memory = self.memory.clone();
+        let result = change(&mut memory)?;
+        if changed(&result) {
+            super::write_atomic(&self.path, &memory.snapshot())?;
+            self.memory = memory;
+        }
+        Ok(result)
+    }
+}
+
+impl TodoStore for JsonStore {
+    fn list(&self) -> StoreResult<Vec<Todo>> {
+        self.memory.list()
+    }
+
+    fn get(&self, id: u32) -> StoreResult<Option<Todo>> {
+        self.memory.get(id)
+    }
+
+    fn insert(&mut self, task: String) -> StoreResult<Todo> {
+        self.commit(|memory| memory.insert(task), |_| true)
+    }
+
+    fn update(&mut self, todo: &Todo) -> StoreResult<bool> {
+        self.commit(|memory| memory.update(todo), |found| *found)
+    }
+
+    fn remove(&mut self, id: u32) -> StoreResult<bool> {
+        self.commit(|memory| memory.remove(id), |found| *found)
+    }
+
+    fn clear_completed(&mut self) -> StoreResult<usize> {
+        self.commit(|memory| memory.clear_completed(), |cleared| *cleared > 0)
+    }
+
+    fn toggle_all(&mut self, done: bool) -> StoreResult<()> {
+        self.commit(|memory| memory.toggle_all(done), |_| true)
+    }
+
+    fn counts(&self) -> StoreResult<Counts> {
+        self.memory.counts()
+    }
+}
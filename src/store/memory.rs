@@ -0,0 +1,124 @@
+use super::{Backend, Counts, StoreResult, TodoStore};
+use crate::Todo;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// serializable form of a MemoryStore, the counter is kept
+// so ids never get reused after a restart
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id_counter: u32,
+    pub todos: Vec<Todo>,
+}
+
+// the todos vector, use Atomic for the id_counter
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    id_counter: AtomicU32,
+    todos: Vec<Todo>,
+}
+
+// the atomic counter can't be derived, go through a snapshot
+impl Clone for MemoryStore {
+    fn clone(&self) -> Self {
+        MemoryStore::from_snapshot(self.snapshot())
+    }
+}
+
+impl MemoryStore {
+    pub fn from_snapshot(snapshot: Snapshot) -> MemoryStore {
+        MemoryStore {
+            id_counter: AtomicU32::new(snapshot.id_counter),
+            todos: snapshot.todos,
+        }
+    }
+
+    // put back a todo that already has an id, when replaying a log,
+    // the counter is moved past it so new ids never collide
+    pub fn push(&mut self, todo: Todo) {
+        self.id_counter.fetch_max(todo.id + 1, Ordering::Relaxed);
+        self.todos.push(todo);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            id_counter: self.id_counter.load(Ordering::Relaxed),
+            todos: self.todos.clone(),
+        }
+    }
+}
+
+impl TodoStore for MemoryStore {
+    fn list(&self) -> StoreResult<Vec<Todo>> {
+        Ok(self.todos.clone())
+    }
+
+    fn get(&self, id: u32) -> StoreResult<Option<Todo>> {
+        Ok(self.todos.iter().find(|t| t.id == id).cloned())
+    }
+
+    fn insert(&mut self, task: String) -> StoreResult<Todo> {
+        let todo = Todo::new_id(task, false, false, &self.id_counter);
+        self.todos.push(todo.clone());
+        Ok(todo)
+    }
+
+    fn update(&mut self, todo: &Todo) -> StoreResult<bool> {
+        match self.todos.iter_mut().find(|t| t.id == todo.id) {
+            Some(stored) => {
+                stored.task = todo.task.clone();
+                stored.done = todo.done;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn remove(&mut self, id: u32) -> StoreResult<bool> {
+        let before = self.todos.len();
+        self.todos.retain(|t| t.id != id);
+        Ok(self.todos.len() != before)
+    }
+
+    fn clear_completed(&mut self) -> StoreResult<usize> {
+        let before = self.todos.len();
+        self.todos.retain(|t| !t.done);
+        Ok(before - self.todos.len())
+    }
+
+    fn toggle_all(&mut self, done: bool) -> StoreResult<()> {
+        for todo in self.todos.iter_mut() {
+            todo.done = done;
+        }
+        Ok(())
+    }
+
+    fn counts(&self) -> StoreResult<Counts> {
+        Ok(Counts {
+            total: self.todos.len(),
+            completed: self.todos.iter().filter(|t| t.done).count(),
+        })
+    }
+}
+
+// keep everything in memory, nothing survive a restart
+#[derive(Debug, Default)]
+pub struct MemoryBackend;
+
+impl Backend for MemoryBackend {
+    fn open(&self, _session_id: &str) -> StoreResult<Box<dyn TodoStore>> {
+        Ok(Box::<MemoryStore>::default())
+    }
+
+    fn sessions(&self) -> StoreResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    fn remove(&self, _session_id: &str) -> StoreResult<()> {
+        Ok(())
+    }
+
+    fn check(&self) -> StoreResult<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use super::{Backend, Counts, StoreError, StoreResult, TodoStore};
+use crate::Todo;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::{
+    io,
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+// every schema change get appended here, never edit one that already shipped,
+// the index + 1 is stored as PRAGMA user_version once applied
+const MIGRATIONS: &[&str] = &[
+    // 1: sessions, todos belong to a session
+    "
+    CREATE TABLE sessions (
+        id TEXT PRIMARY KEY NOT NULL,
+        created_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+    );
+    CREATE TABLE todos (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        session_id TEXT NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+        task TEXT NOT NULL,
+        done INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX todos_session_id ON todos (session_id);
+    ",
+];
+
+// apply every migration newer than the database, each one in its own transaction
+// so a failed migration leave the schema at the previous version
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+// quick_check walk every page of the file, a damaged database answer with what
+// is wrong instead of ok, done once on startup since it hold the connection for
+// as long as it take
+fn integrity_check(conn: &Connection) -> StoreResult<()> {
+    let result: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+    if result != "ok" {
+        return Err(StoreError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("database failed its integrity check: {}", result),
+        )));
+    }
+    Ok(())
+}
+
+fn todo_from_row(row: &Row) -> rusqlite::Result<Todo> {
+    Ok(Todo {
+        id: row.get(0)?,
+        task: row.get(1)?,
+        done: row.get(2)?,
+        editing: false,
+    })
+}
+
+// a single database file hold every session, the connection is shared by all stores
+pub struct SqliteBackend {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteBackend {
+    pub fn open(path: impl AsRef<Path>) -> StoreResult<SqliteBackend> {
+        let mut conn = Connection::open(path)?;
+        // WAL survive a crash mid write, foreign keys are off by default in sqlite
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        integrity_check(&conn)?;
+        migrate(&mut conn)?;
+        Ok(SqliteBackend {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Backend for SqliteBackend {
+    // the session row is only written with its first todo
+    fn open(&self, session_id: &str) -> StoreResult<Box<dyn TodoStore>> {
+        Ok(Box::new(SqliteStore {
+            conn: Arc::clone(&self.conn),
+            session_id: session_id.to_string(),
+        }))
+    }
+
+    fn sessions(&self) -> StoreResult<Vec<String>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT id FROM sessions")?;
+        let session_ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(session_ids)
+    }
+
+    // the session's todos go with it through ON DELETE CASCADE
+    fn remove(&self, session_id: &str) -> StoreResult<()> {
+        self.conn()
+            .execute("DELETE FROM sessions WHERE id = ?1", params![session_id])?;
+        Ok(())
+    }
+
+    // a write rolled straight back, the database is still there and writable
+    fn check(&self) -> StoreResult<()> {
+        self.conn().execute_batch(
+            "
+            SAVEPOINT probe;
+            INSERT INTO sessions (id) VALUES ('.probe');
+            ROLLBACK TO probe;
+            RELEASE probe;
+            ",
+        )?;
+        Ok(())
+    }
+}
+
+// every call go straight to the database, ids are assigned by sqlite
+struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+    session_id: String,
+}
+
+impl SqliteStore {
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl TodoStore for SqliteStore {
+    fn list(&self) -> StoreResult<Vec<Todo>> {
+        let conn = self.conn();
+        let mut stmt =
+            conn.prepare("SELECT id, task, done FROM todos WHERE session_id = ?1 ORDER BY id")?;
+        let todos = stmt
+            .query_map(params![self.session_id], todo_from_row)?
+            .collect::<rusqlite::Result<Vec<Todo>>>()?;
+        Ok(todos)
+    }
+
+    fn get(&self, id: u32) -> StoreResult<Option<Todo>> {
+        let todo = self
+            .conn()
+            .query_row(
+                "SELECT id, task, done FROM todos WHERE session_id = ?1 AND id = ?2",
+                params![self.session_id, id],
+                todo_from_row,
+            )
+            .optional()?;
+        Ok(todo)
+    }
+
+    // the todo need its session row, created here the first time
+    fn insert(&mut self, task: String) -> StoreResult<Todo> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR IGNORE INTO sessions (id) VALUES (?1)",
+            params![self.session_id],
+        )?;
+        tx.execute(
+            "INSERT INTO todos (session_id, task, done) VALUES (?1, ?2, 0)",
+            params![self.session_id, task],
+        )?;
+        // ids are u32 everywhere else, a rowid past that is refused and the
+        // transaction rolled back rather than wrapped onto another todo's id
+        let rowid = tx.last_insert_rowid();
+        let id =
+            u32::try_from(rowid).map_err(|_| rusqlite::Error::IntegralValueOutOfRange(0, rowid))?;
+        tx.commit()?;
+        Ok(Todo {
+            id,
+            task,
+            done: false,
+            editing: false,
+        })
+    }
+
+    fn update(&mut self, todo: &Todo) -> StoreResult<bool> {
+        let changed = self.conn().execute(
+            "UPDATE todos SET task = ?1, done = ?2 WHERE session_id = ?3 AND id = ?4",
+            params![todo.task, todo.done, self.session_id, todo.id],
+        )?;
+        Ok(changed != 0)
+    }
+
+    fn remove(&mut self, id: u32) -> StoreResult<bool> {
+        let changed = self.conn().execute(
+            "DELETE FROM todos WHERE session_id = ?1 AND id = ?2",
+            params![self.session_id, id],
+        )?;
+        Ok(changed != 0)
+    }
+
+    fn clear_completed(&mut self) -> StoreResult<usize> {
+        let changed = self.conn().execute(
+            "DELETE FROM todos WHERE session_id = ?1 AND done = 1",
+            params![self.session_id],
+        )?;
+        Ok(changed)
+    }
+
+    fn toggle_all(&mut self, done: bool) -> StoreResult<()> {
+        self.conn().execute(
+            "UPDATE todos SET done = ?1 WHERE session_id = ?2",
+            params![done, self.session_id],
+        )?;
+        Ok(())
+    }
+
+    fn counts(&self) -> StoreResult<Counts> {
+        let (total, completed): (usize, usize) = self.conn().query_row(
+            "SELECT COUNT(*), COALESCE(SUM(done), 0) FROM todos WHERE session_id = ?1",
+            params![self.session_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(Counts { total, completed })
+    }
+}